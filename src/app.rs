@@ -7,43 +7,97 @@
 use crossbeam_channel::{unbounded, Receiver, Sender};
 use gio::{self, prelude::*};
 use gtk::prelude::*;
-use gtk::{ApplicationWindow, Builder, Overlay};
+use gtk::{
+    ApplicationWindow, Builder, FileChooserAction, FileChooserNative, FileFilter, Overlay,
+    ResponseType,
+};
 
 use crate::widgets::header::*;
 use crate::{
     db::*,
+    logger,
     ssr::*,
     view::*,
     widgets::{mark_all_notif, notice::InAppNotification},
 };
-use futures::stream::{FuturesUnordered, StreamExt};
+use futures::future;
+use futures::stream::{self, StreamExt};
 use smol_timeout::TimeoutExt;
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::env;
+use std::fs;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Clone)]
 pub(crate) enum Action {
     ConnectSSR,
-    Subscription(Vec<(String, Option<String>, Vec<SsrConfig>)>),
-    SubscriptionInit(String),
+    Connected,
+    ConnectFailed(String),
+    DisconnectSSR,
+    Subscription(u64, Vec<Group>),
+    SubscriptionInit(String, SubOptions),
+    EditSubInit,
+    EditSub(u64, String, SubOptions),
     AddSSRUrl(String),
     AddConfig(SsrConfig),
-    RefreshSsrListView(u8),
-    UpdateHomeSsrListRow(Option<u8>),
+    EditConfigInit(u64),
+    EditConfig(u64, SsrConfig),
+    CopySsrUrl(u64),
+    ShowQrcode(u64),
+    RefreshSsrListView(u64),
+    UpdateHomeSsrListRow(Option<u64>),
     RefreshHomeSidebar,
     SpeedInt,
-    Speed(Vec<(String, Option<String>, Vec<SsrConfig>)>),
+    RealSpeedInt,
+    SpeedResult(u64, Latency),
+    SpeedFinished(u64, Vec<(u64, Latency)>),
+    SortSsrList(String, bool),
+    SetAutoSelect(bool),
+    AutoSelectResult(u64, Vec<(u64, Latency)>),
+    HealthCheck(u64, Option<String>),
+    FailOver(u64, Option<u64>),
     RefreshSubInt,
-    RefreshSub(Vec<(String, Option<String>, Vec<SsrConfig>)>),
+    RefreshAllSubs,
+    RefreshSub(u64, Vec<SsrConfig>, SubInfo),
+    SetUpdateInterval(u32),
     RemoveGroup,
-    RemoveSSR,
+    RemoveSSR(u64),
     Qrcode,
+    QrcodeFile,
+    QrcodeClipboard,
+    QrcodeImages(Vec<Vec<u8>>),
     ShowNotice(String),
 }
 
+// 连接稳定运行超过该时长后重置重连次数
+const STABLE_SECS: u64 = 60;
+// 检查订阅是否需要自动更新的间隔
+const SUB_CHECK_SECS: u64 = 60;
+
+// 连接监控状态
+#[derive(Clone, Default)]
+struct Watchdog {
+    // 当前连接的节点, 为 None 时表示未连接
+    node_id: Option<u64>,
+    // 已重连次数
+    retries: u32,
+    // 下次重连时间
+    restart_at: Option<Instant>,
+    // 最近一次连接成功的时间
+    connected_at: Option<Instant>,
+    // 下次自动选择节点的时间
+    select_at: Option<Instant>,
+    // 正在为本次连接自动选择节点
+    selecting: bool,
+    // 下次健康检查的时间, 检查进行中为 None
+    check_at: Option<Instant>,
+    // 健康检查连续失败次数
+    failures: u32,
+}
+
 #[derive(Clone)]
 pub(crate) struct App {
     window: gtk::ApplicationWindow,
@@ -51,6 +105,11 @@ pub(crate) struct App {
     header: Rc<Header>,
     notice: RefCell<Option<InAppNotification>>,
     overlay: Overlay,
+    watchdog: RefCell<Watchdog>,
+    // 本次运行中各订阅最近一次尝试自动更新的时间, 避免失败后反复重试
+    sub_attempts: RefCell<HashMap<u64, u64>>,
+    // 打开中的二维码图片选择对话框
+    qrcode_chooser: RefCell<Option<FileChooserNative>>,
     sender: Sender<Action>,
     receiver: Receiver<Action>,
 }
@@ -71,6 +130,7 @@ impl App {
         window.set_title("SSR-GTK");
 
         let view = View::new(&builder, &sender, data.clone());
+        view.set_home_sort(&Data::new().get_settings());
         let header = Header::new(&builder, &sender, data);
 
         window.show_all();
@@ -86,6 +146,42 @@ impl App {
             Inhibit(false)
         });
 
+        // 拖放二维码图片
+        window.drag_dest_set(gtk::DestDefaults::ALL, &[], gdk::DragAction::COPY);
+        window.drag_dest_add_uri_targets();
+        window.drag_dest_add_image_targets();
+        let sender_clone = sender.clone();
+        window.connect_drag_data_received(move |_, _, _, _, data, _, _| {
+            let mut images = read_image_uris(&data.get_uris());
+            if let Some(pixbuf) = data.get_pixbuf() {
+                if let Ok(buffer) = pixbuf.save_to_bufferv("png", &[]) {
+                    images.push(buffer);
+                }
+            }
+            sender_clone
+                .send(Action::QrcodeImages(images))
+                .unwrap_or(());
+        });
+
+        // Ctrl+V 粘贴二维码图片, 焦点在输入框且剪贴板中不是图片时保留默认的粘贴
+        let sender_clone = sender.clone();
+        window.connect_key_press_event(move |window, event| {
+            if event.get_state().contains(gdk::ModifierType::CONTROL_MASK)
+                && event.get_keyval() == gdk::keys::constants::v
+            {
+                let editing =
+                    matches!(window.get_focus(), Some(widget) if widget.is::<gtk::Editable>());
+                if editing
+                    && !gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD).wait_is_image_available()
+                {
+                    return Inhibit(false);
+                }
+                sender_clone.send(Action::QrcodeClipboard).unwrap_or(());
+                return Inhibit(true);
+            }
+            Inhibit(false)
+        });
+
         let overlay: Overlay = builder.get_object("overlay").unwrap();
 
         let notice = RefCell::new(None);
@@ -96,6 +192,9 @@ impl App {
             view,
             notice,
             overlay,
+            watchdog: RefCell::new(Watchdog::default()),
+            sub_attempts: RefCell::new(HashMap::new()),
+            qrcode_chooser: RefCell::new(None),
             sender,
             receiver,
         };
@@ -105,6 +204,17 @@ impl App {
     fn init(app: &Rc<Self>) {
         // Setup the Action channel
         glib::timeout_add_local(25, crate::clone!(app => move || app.setup_action_channel()));
+        // 监控 ssr-local 运行状态
+        glib::timeout_add_local(1000, crate::clone!(app => move || app.check_connection()));
+        // 启动时更新全部开启自动更新的订阅, 之后按各订阅的间隔更新
+        app.check_subs(true);
+        glib::timeout_add_local(
+            SUB_CHECK_SECS as u32 * 1000,
+            crate::clone!(app => move || {
+                app.check_subs(false);
+                glib::Continue(true)
+            }),
+        );
     }
 
     fn setup_action_channel(&self) -> glib::Continue {
@@ -121,48 +231,120 @@ impl App {
         match action {
             Action::ConnectSSR => {
                 let (gid, sid) = self.view.get_home_gr_id();
-                if gid.is_some() && sid.is_some() {
-                    let db = Data::new();
-                    if let Some(configs) = db.get_all() {
-                        if !run(&configs[gid.unwrap() as usize].2[sid.unwrap_or(0) as usize]) {
-                            self.header.disc_button_active();
-                            self.sender
-                                .send(Action::ShowNotice("连接失败!".to_owned()))
-                                .unwrap_or(());
-                        }
-                    } else {
-                        self.header.disc_button_active();
-                        self.sender
-                            .send(Action::ShowNotice("连接失败!".to_owned()))
-                            .unwrap_or(());
-                    }
+                if is_external_run() {
+                    self.sender
+                        .send(Action::ShowNotice(
+                            "检测到由其他程序启动的 ssr-local, 本地端口可能被占用!".to_owned(),
+                        ))
+                        .unwrap_or(());
+                }
+                let auto_select = gid
+                    .and_then(|id| Data::new().get_group(id))
+                    .filter(|group| group.auto_select);
+                if let Some(group) = auto_select {
+                    // 自动选择模式下连接延迟最低的节点
+                    self.watchdog.replace(Watchdog {
+                        selecting: true,
+                        ..Watchdog::default()
+                    });
+                    self.header.set_connecting();
+                    self.auto_select(group.id);
+                } else if let Some(node_id) = sid {
+                    self.watchdog.replace(Watchdog {
+                        node_id: Some(node_id),
+                        ..Watchdog::default()
+                    });
+                    self.connect(node_id);
                 } else {
-                    self.header.disc_button_active();
+                    self.header.set_connected(false);
                     self.sender
                         .send(Action::ShowNotice("请先选中要连接的 ssr 条目!".to_owned()))
                         .unwrap_or(());
                 }
             }
-            Action::Subscription(configs) => {
-                self.view
-                    .update_home_sidebar((configs.len() - 1) as u8, &configs);
+            Action::Connected => {
+                // 等待期间已断开, 关闭断开后才启动的进程
+                if self.watchdog.borrow().node_id.is_none() {
+                    self.stop();
+                    return glib::Continue(true);
+                }
+                self.watchdog.borrow_mut().connected_at = Some(Instant::now());
+                self.schedule_auto_select();
+                self.schedule_health_check();
+                self.header.set_connected(true);
+                self.sender
+                    .send(Action::ShowNotice("连接成功!".to_owned()))
+                    .unwrap_or(());
+            }
+            Action::ConnectFailed(e) => {
+                if self.watchdog.borrow().node_id.is_none() {
+                    return glib::Continue(true);
+                }
+                logger::append(&format!("连接失败: {}", e));
+                // 重连失败时继续重连
+                let restart = if self.watchdog.borrow().retries > 0 {
+                    self.schedule_restart()
+                } else {
+                    None
+                };
+                let notice = match restart {
+                    Some(secs) => format!("连接失败: {} {} 秒后重连!", e, secs),
+                    None => {
+                        self.watchdog.replace(Watchdog::default());
+                        self.header.set_connected(false);
+                        format!("连接失败: {}", e)
+                    }
+                };
+                self.sender.send(Action::ShowNotice(notice)).unwrap_or(());
+            }
+            Action::DisconnectSSR => {
+                self.watchdog.replace(Watchdog::default());
+                self.stop();
             }
-            Action::SubscriptionInit(url) => {
+            Action::Subscription(group_id, configs) => {
+                self.view.update_home_sidebar(Some(group_id), &configs);
+            }
+            Action::SubscriptionInit(url, options) => {
                 let sender = self.sender.clone();
                 smol::Task::spawn(async move {
-                    if let Some(configs) = add_sub(url).await {
-                        sender.send(Action::Subscription(configs)).unwrap_or(());
-                    } else {
-                        sender
-                            .send(Action::ShowNotice("添加订阅失败!".to_owned()))
-                            .unwrap_or(());
+                    match add_sub(url, options).await {
+                        Ok((group_id, configs)) => sender
+                            .send(Action::Subscription(group_id, configs))
+                            .unwrap_or(()),
+                        Err(e) => {
+                            logger::append(&format!("添加订阅失败: {}", e));
+                            sender
+                                .send(Action::ShowNotice(format!("添加订阅失败: {}!", e)))
+                                .unwrap_or(());
+                        }
                     }
                 })
                 .detach();
             }
+            Action::EditSubInit => {
+                let (gid, _) = self.view.get_home_gr_id();
+                if let Some(group) = gid.and_then(|id| Data::new().get_group(id)) {
+                    if let Some(url) = &group.url {
+                        self.header
+                            .show_sub_dialog(group.id, url, &group.sub_options);
+                    }
+                }
+            }
+            Action::EditSub(group_id, url, options) => {
+                let mut db = Data::new();
+                if db.edit_sub(group_id, url, options).is_some() {
+                    self.sender
+                        .send(Action::ShowNotice("订阅设置已保存!".to_owned()))
+                        .unwrap_or(());
+                } else {
+                    self.sender
+                        .send(Action::ShowNotice("保存订阅设置失败!".to_owned()))
+                        .unwrap_or(());
+                }
+            }
             Action::AddSSRUrl(url) => {
                 if let Some((group_id, configs)) = add_ssr_url(url) {
-                    self.view.update_home_sidebar(group_id, &configs);
+                    self.view.update_home_sidebar(Some(group_id), &configs);
                     self.sender
                         .send(Action::ShowNotice("添加成功!".to_owned()))
                         .unwrap_or(());
@@ -172,11 +354,65 @@ impl App {
                         .unwrap_or(());
                 }
             }
-            Action::AddConfig(config) => println!("{:?}", config),
+            Action::AddConfig(config) => {
+                if let Some((group_id, configs)) = add_config(config) {
+                    self.view.update_home_sidebar(Some(group_id), &configs);
+                    self.sender
+                        .send(Action::ShowNotice("添加成功!".to_owned()))
+                        .unwrap_or(());
+                } else {
+                    self.sender
+                        .send(Action::ShowNotice("添加配置失败!".to_owned()))
+                        .unwrap_or(());
+                }
+            }
+            Action::EditConfigInit(node_id) => {
+                let db = Data::new();
+                if let Some((group, node)) = db.get_node(node_id) {
+                    self.header.show_edit_dialog(
+                        node_id,
+                        &group.name,
+                        &node.config,
+                        group.url.is_some(),
+                    );
+                }
+            }
+            Action::EditConfig(node_id, config) => {
+                if let Some((group_id, configs)) = edit_config(node_id, config) {
+                    self.view.update_home_ssr_list(group_id, &configs);
+                    self.sender
+                        .send(Action::ShowNotice("修改成功!".to_owned()))
+                        .unwrap_or(());
+                } else {
+                    self.sender
+                        .send(Action::ShowNotice("修改配置失败!".to_owned()))
+                        .unwrap_or(());
+                }
+            }
+            Action::CopySsrUrl(node_id) => {
+                let db = Data::new();
+                if let Some(config) = db.get_config(node_id) {
+                    let clipboard = gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD);
+                    clipboard.set_text(&ssr_url_encode(&config));
+                    self.sender
+                        .send(Action::ShowNotice("已复制链接到剪贴板!".to_owned()))
+                        .unwrap_or(());
+                }
+            }
+            Action::ShowQrcode(node_id) => {
+                let db = Data::new();
+                if let Some(config) = db.get_config(node_id) {
+                    if !self.header.show_qrcode_dialog(&config) {
+                        self.sender
+                            .send(Action::ShowNotice("生成二维码失败!".to_owned()))
+                            .unwrap_or(());
+                    }
+                }
+            }
             Action::RefreshHomeSidebar => {
                 let db = Data::new();
                 if let Some(configs) = db.get_all() {
-                    self.view.update_home_sidebar(0, &configs);
+                    self.view.update_home_sidebar(None, &configs);
                 }
             }
             Action::RefreshSsrListView(id) => {
@@ -190,122 +426,251 @@ impl App {
                 let (gid, _) = self.view.get_home_gr_id();
                 if let Some(id) = gid {
                     let db = Data::new();
-                    if let Some(mut configs) = db.get_all() {
+                    if let Some(group) = db.get_group(id) {
                         let sender_clone = self.sender.clone();
                         smol::Task::spawn(async move {
-                            if let Some(value) = configs.get(id as usize) {
-                                if let Ok(config) =
-                                    ssr_sub_url_parse(&value.1.as_ref().unwrap_or(&String::new()))
-                                        .await
-                                {
-                                    configs[id as usize] =
-                                        (value.0.to_owned(), value.1.to_owned(), config);
-                                    sender_clone.send(Action::RefreshSub(configs)).unwrap_or(());
-                                } else {
-                                    sender_clone
-                                        .send(Action::ShowNotice("更新订阅失败!".to_owned()))
-                                        .unwrap_or(());
-                                }
-                            }
+                            refresh_sub(group, &sender_clone).await;
                         })
                         .detach();
                     }
                 }
             }
-            Action::RefreshSub(configs) => {
-                let (gid, _) = self.view.get_home_gr_id();
-                if let Some(id) = gid {
-                    let mut db = Data::new();
-                    db.set_all(&configs).ok();
-                    self.view.update_home_ssr_list(id, &configs);
+            Action::RefreshAllSubs => {
+                let groups = Data::new()
+                    .get_all()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|group| group.url.is_some())
+                    .collect::<Vec<Group>>();
+                if groups.is_empty() {
                     self.sender
-                        .send(Action::ShowNotice("更新订阅成功!".to_owned()))
+                        .send(Action::ShowNotice("没有可更新的订阅!".to_owned()))
                         .unwrap_or(());
+                    return glib::Continue(true);
                 }
+                self.sender
+                    .send(Action::ShowNotice("正在更新全部订阅...".to_owned()))
+                    .unwrap_or(());
+                let sender_clone = self.sender.clone();
+                smol::Task::spawn(async move {
+                    future::join_all(
+                        groups
+                            .into_iter()
+                            .map(|group| refresh_sub(group, &sender_clone)),
+                    )
+                    .await;
+                })
+                .detach();
             }
-            Action::SpeedInt => {
-                let (gid, _) = self.view.get_home_gr_id();
-                if let Some(id) = gid {
-                    let db = Data::new();
-                    if let Some(mut configs) = db.get_all() {
-                        let sender_clone = self.sender.clone();
-                        smol::Task::spawn(async move {
-                            if let Some(value) = configs.get_mut(id as usize) {
-                                let mut cf = FuturesUnordered::new();
-                                for config in &value.2 {
-                                    let host = config.remote_addr.to_owned();
-                                    let port = config.remote_port.to_owned();
-                                    cf.push(timeout(host, port).timeout(Duration::from_secs(3)));
-                                }
-                                for config in value.2.iter_mut() {
-                                    if let Some(t) = cf.next().await {
-                                        match t {
-                                            Some(t) => match t {
-                                                Ok(time) => config.delay = format!("{} ms", time),
-                                                Err(_) => config.delay = String::from("超时"),
-                                            },
-                                            None => config.delay = String::from("超时"),
-                                        }
-                                    }
-                                }
-                                sender_clone.send(Action::Speed(configs)).unwrap_or(());
-                            }
-                        })
-                        .detach();
+            Action::RefreshSub(id, config, info) => {
+                let mut db = Data::new();
+                let name = db.get_group(id).map(|group| group.name).unwrap_or_default();
+                let keep = self.watchdog.borrow().node_id;
+                if let Some((diff, configs)) = db.update_sub(id, config, info, keep) {
+                    // 后台更新时不切换当前显示的分组
+                    let (gid, _) = self.view.get_home_gr_id();
+                    if gid == Some(id) {
+                        self.view.update_home_ssr_list(id, &configs);
                     }
+                    logger::append(&format!("订阅 {} 已更新: {}", name, diff));
+                    let removed = configs
+                        .iter()
+                        .flat_map(|group| group.nodes.iter())
+                        .any(|node| {
+                            Some(node.id) == keep && node.change == Some(NodeChange::Removed)
+                        });
+                    let notice = if removed {
+                        format!("订阅 {} 已更新: {}, 当前节点已从订阅中移除!", name, diff)
+                    } else {
+                        format!("订阅 {} 已更新: {}", name, diff)
+                    };
+                    self.sender.send(Action::ShowNotice(notice)).unwrap_or(());
+                } else {
+                    self.sender
+                        .send(Action::ShowNotice(format!("更新订阅 {} 失败!", name)))
+                        .unwrap_or(());
                 }
             }
-            Action::Speed(configs) => {
+            Action::SetUpdateInterval(hours) => {
                 let (gid, _) = self.view.get_home_gr_id();
-                if let Some(id) = gid {
-                    let mut db = Data::new();
-                    db.set_all(&configs).ok();
-                    self.view.update_home_ssr_list(id, &configs);
+                let mut db = Data::new();
+                let group = match gid.and_then(|id| db.get_group(id)) {
+                    Some(group) => group,
+                    None => return glib::Continue(true),
+                };
+                // 切换分组时同步数值, 无需处理
+                if group.update_interval == hours {
+                    return glib::Continue(true);
+                }
+                if db.set_update_interval(group.id, hours).is_none() {
                     self.sender
-                        .send(Action::ShowNotice("测速完成!".to_string()))
+                        .send(Action::ShowNotice("保存设置失败!".to_owned()))
                         .unwrap_or(());
                 }
             }
-            Action::RemoveGroup => {
+            Action::SpeedInt => self.speed_test(false),
+            Action::RealSpeedInt => self.speed_test(true),
+            // 测速过程中只更新界面, 结果在完成后一次性保存
+            Action::SpeedResult(node_id, latency) => {
+                self.view.update_home_ssr_latency(node_id, &latency);
+            }
+            Action::SpeedFinished(group_id, results) => {
+                if Data::new().set_latencies(&results).is_none() {
+                    logger::append("保存测速结果失败");
+                }
+                // 按新的测速结果重新排序及过滤, 测速期间切换了分组时不刷新
+                if self.view.get_home_gr_id().0 == Some(group_id) {
+                    self.sender
+                        .send(Action::RefreshSsrListView(group_id))
+                        .unwrap_or(());
+                }
+                self.sender
+                    .send(Action::ShowNotice("测速完成!".to_string()))
+                    .unwrap_or(());
+            }
+            Action::SortSsrList(sort_by, hide_timeout) => {
+                let mut db = Data::new();
+                let mut settings = db.get_settings();
+                settings.sort_by = sort_by;
+                settings.hide_timeout = hide_timeout;
+                db.set_settings(&settings).ok();
                 let (gid, _) = self.view.get_home_gr_id();
                 if let Some(id) = gid {
+                    self.sender
+                        .send(Action::RefreshSsrListView(id))
+                        .unwrap_or(());
+                }
+            }
+            Action::SetAutoSelect(auto_select) => {
+                let (gid, _) = self.view.get_home_gr_id();
+                let node_id = self.watchdog.borrow().node_id;
+                // 读取并保存后关闭数据库, auto_select 会再次打开数据库
+                let (group, connected) = {
                     let mut db = Data::new();
-                    if let Some(mut configs) = db.get_all() {
-                        configs.remove(id as usize);
-                        db.set_all(&configs).ok();
-                        self.view.update_home_sidebar(0, &configs);
+                    let group = match gid.and_then(|id| db.get_group(id)) {
+                        Some(group) => group,
+                        None => return glib::Continue(true),
+                    };
+                    // 切换分组时同步勾选状态, 无需处理
+                    if group.auto_select == auto_select {
+                        return glib::Continue(true);
+                    }
+                    if db.set_auto_select(group.id, auto_select).is_none() {
+                        self.sender
+                            .send(Action::ShowNotice("保存设置失败!".to_owned()))
+                            .unwrap_or(());
+                        return glib::Continue(true);
                     }
+                    let connected = node_id
+                        .and_then(|id| db.get_node(id))
+                        .map(|(node_group, _)| node_group.id == group.id)
+                        .unwrap_or(false);
+                    (group, connected)
+                };
+                // 当前连接的节点属于该分组时立即选择一次
+                if auto_select && connected {
+                    self.auto_select(group.id);
                 }
             }
-            Action::RemoveSSR => {
-                let (gid, rid) = self.view.get_home_gr_id();
-                if let Some(gid) = gid {
+            Action::AutoSelectResult(group_id, results) => {
+                self.auto_select_result(group_id, &results)
+            }
+            Action::HealthCheck(node_id, error) => self.health_check_result(node_id, error),
+            Action::FailOver(node_id, next) => {
+                if self.watchdog.borrow().node_id != Some(node_id) {
+                    return glib::Continue(true);
+                }
+                // 先读取节点名称并关闭数据库, connect 等调用会再次打开数据库
+                let (current, next_remarks) = {
+                    let db = Data::new();
+                    let remarks = |id| {
+                        db.get_config(id)
+                            .map(|config| config.remarks)
+                            .unwrap_or_default()
+                    };
+                    (remarks(node_id), next.map(remarks))
+                };
+                let notice = match (next, next_remarks) {
+                    (Some(next_id), Some(next_remarks)) => {
+                        logger::append(&format!(
+                            "节点 {} 不可用, 切换到 {}",
+                            current, next_remarks
+                        ));
+                        self.watchdog.replace(Watchdog {
+                            node_id: Some(next_id),
+                            ..Watchdog::default()
+                        });
+                        self.connect(next_id);
+                        self.view.update_home_ssr_list_row_id(Some(next_id));
+                        format!("节点 {} 不可用, 已切换到 {}!", current, next_remarks)
+                    }
+                    _ => {
+                        logger::append(&format!("节点 {} 不可用, 同组没有其他可用节点", current));
+                        self.watchdog.borrow_mut().failures = 0;
+                        self.schedule_health_check();
+                        format!("节点 {} 不可用, 同组没有其他可用节点!", current)
+                    }
+                };
+                self.sender.send(Action::ShowNotice(notice)).unwrap_or(());
+            }
+            Action::RemoveGroup => {
+                let (gid, _) = self.view.get_home_gr_id();
+                if let Some(id) = gid {
                     let mut db = Data::new();
-                    if let Some(mut configs) = db.get_all() {
-                        if let Some(rid) = rid {
-                            configs[gid as usize].2.remove(rid as usize);
-                            db.set_all(&configs).ok();
-                            self.view.update_home_sidebar(gid, &configs);
-                        } else {
-                            self.sender
-                                .send(Action::ShowNotice("请先选中要删除的条目:)".to_string()))
-                                .unwrap_or(());
-                        }
+                    if let Some(configs) = db.remove_group(id) {
+                        self.view.update_home_sidebar(None, &configs);
                     }
                 }
             }
-            Action::Qrcode => {
-                if let Some((group_id, configs)) = add_qrcode() {
-                    self.view.update_home_sidebar(group_id, &configs);
-                    self.sender
-                        .send(Action::ShowNotice("添加成功!".to_owned()))
-                        .unwrap_or(());
+            Action::RemoveSSR(node_id) => {
+                let mut db = Data::new();
+                if let Some((group_id, configs)) = db.remove_node(node_id) {
+                    self.view.update_home_sidebar(Some(group_id), &configs);
                 } else {
                     self.sender
-                        .send(Action::ShowNotice("扫码添加失败!".to_owned()))
+                        .send(Action::ShowNotice("删除节点失败!".to_string()))
                         .unwrap_or(());
                 }
             }
+            Action::Qrcode => self.qrcode_result(add_qrcode()),
+            Action::QrcodeFile => {
+                let chooser = FileChooserNative::new(
+                    Some("选择二维码图片"),
+                    Some(&self.window),
+                    FileChooserAction::Open,
+                    Some("打开"),
+                    Some("取消"),
+                );
+                chooser.set_select_multiple(true);
+                let filter = FileFilter::new();
+                filter.set_name(Some("图片"));
+                filter.add_pixbuf_formats();
+                chooser.add_filter(&filter);
+                // 由消息循环调用, 不能使用 run() 阻塞, 选择结果通过 QrcodeImages 返回
+                let sender = self.sender.clone();
+                chooser.connect_response(move |chooser, response| {
+                    if response == ResponseType::Accept {
+                        let images = chooser
+                            .get_filenames()
+                            .iter()
+                            .filter_map(|path| fs::read(path).ok())
+                            .collect::<Vec<Vec<u8>>>();
+                        sender.send(Action::QrcodeImages(images)).unwrap_or(());
+                    }
+                });
+                chooser.show();
+                // 对话框关闭前需保留引用
+                self.qrcode_chooser.replace(Some(chooser));
+            }
+            Action::QrcodeClipboard => {
+                let clipboard = gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD);
+                let images = match clipboard.wait_for_image() {
+                    Some(pixbuf) => pixbuf.save_to_bufferv("png", &[]).into_iter().collect(),
+                    None => read_image_uris(&clipboard.wait_for_uris()),
+                };
+                self.qrcode_result(add_qrcode_images(&images));
+            }
+            Action::QrcodeImages(images) => self.qrcode_result(add_qrcode_images(&images)),
             Action::ShowNotice(text) => {
                 let notif = mark_all_notif(text);
                 let old = self.notice.replace(Some(notif));
@@ -317,6 +682,468 @@ impl App {
         glib::Continue(true)
     }
 
+    // 启动节点并等待本地端口就绪, 结果通过 Connected/ConnectFailed 返回
+    fn connect(&self, node_id: u64) {
+        let db = Data::new();
+        let config = match db.get_config(node_id) {
+            Some(config) => config,
+            None => {
+                self.sender
+                    .send(Action::ConnectFailed("节点不存在!".to_owned()))
+                    .unwrap_or(());
+                return;
+            }
+        };
+        self.header.set_connecting();
+        // 关闭旧进程需要等待, 在后台任务中启动
+        let sender = self.sender.clone();
+        smol::Task::spawn(async move {
+            let result = match run(&config).await {
+                // 等待本地端口响应 SOCKS5 握手后才算连接成功
+                Ok(()) => wait_ready(&config, Duration::from_secs(5)).await,
+                Err(e) => Err(e),
+            };
+            let action = match result {
+                Ok(()) => Action::Connected,
+                Err(e) => Action::ConnectFailed(e.to_string()),
+            };
+            sender.send(action).unwrap_or(());
+        })
+        .detach();
+    }
+
+    // 在后台关闭 ssr-local, 失败时通过 ShowNotice 提示
+    fn stop(&self) {
+        let sender = self.sender.clone();
+        smol::Task::spawn(async move {
+            if let Err(e) = stop().await {
+                logger::append(&format!("断开连接失败: {}", e));
+                sender
+                    .send(Action::ShowNotice(format!("断开连接失败: {}", e)))
+                    .unwrap_or(());
+            }
+        })
+        .detach();
+    }
+
+    // 按设置安排重连, 返回等待秒数, 不再重连时返回 None
+    fn schedule_restart(&self) -> Option<u64> {
+        let settings = Data::new().get_settings();
+        let mut watchdog = self.watchdog.borrow_mut();
+        if watchdog.node_id.is_none()
+            || !settings.auto_restart
+            || watchdog.retries >= settings.max_restarts
+        {
+            return None;
+        }
+        // 1, 2, 4 ... 最长 60 秒
+        let secs = (1u64 << watchdog.retries.min(6)).min(60);
+        watchdog.restart_at = Some(Instant::now() + Duration::from_secs(secs));
+        logger::append(&format!(
+            "{} 秒后进行第 {} 次重连",
+            secs,
+            watchdog.retries + 1
+        ));
+        Some(secs)
+    }
+
+    // 检测 ssr-local 是否意外退出, 并按计划重连
+    fn check_connection(&self) -> glib::Continue {
+        let restart = {
+            let mut watchdog = self.watchdog.borrow_mut();
+            match (watchdog.node_id, watchdog.restart_at) {
+                (Some(node_id), Some(at)) if Instant::now() >= at => {
+                    watchdog.restart_at = None;
+                    watchdog.retries += 1;
+                    Some((node_id, watchdog.retries))
+                }
+                _ => None,
+            }
+        };
+        if let Some((node_id, retries)) = restart {
+            logger::append(&format!("第 {} 次重连", retries));
+            self.connect(node_id);
+        } else if self.header.is_connected() && !is_run() {
+            logger::append("ssr-local 意外退出");
+            {
+                let mut watchdog = self.watchdog.borrow_mut();
+                let stable = watchdog
+                    .connected_at
+                    .map(|at| at.elapsed() >= Duration::from_secs(STABLE_SECS))
+                    .unwrap_or(false);
+                if stable {
+                    watchdog.retries = 0;
+                }
+            }
+            let notice = match self.schedule_restart() {
+                Some(secs) => {
+                    self.header.set_connecting();
+                    format!("连接已断开, {} 秒后重连!", secs)
+                }
+                None => {
+                    self.watchdog.replace(Watchdog::default());
+                    self.header.set_connected(false);
+                    "连接已断开!".to_owned()
+                }
+            };
+            self.sender.send(Action::ShowNotice(notice)).unwrap_or(());
+        }
+        // 定时自动选择节点
+        let select = {
+            let mut watchdog = self.watchdog.borrow_mut();
+            match (watchdog.node_id, watchdog.select_at) {
+                (Some(node_id), Some(at))
+                    if Instant::now() >= at && watchdog.restart_at.is_none() =>
+                {
+                    watchdog.select_at = None;
+                    Data::new().get_node(node_id).map(|(group, _)| group.id)
+                }
+                _ => None,
+            }
+        };
+        if let Some(group_id) = select {
+            self.auto_select(group_id);
+        }
+        // 定时健康检查
+        let check = {
+            let mut watchdog = self.watchdog.borrow_mut();
+            match (watchdog.node_id, watchdog.check_at) {
+                (Some(node_id), Some(at))
+                    if Instant::now() >= at && watchdog.restart_at.is_none() =>
+                {
+                    watchdog.check_at = None;
+                    Some(node_id)
+                }
+                _ => None,
+            }
+        };
+        if let Some(node_id) = check {
+            if self.header.is_connected() {
+                self.health_check(node_id);
+            } else {
+                self.schedule_health_check();
+            }
+        }
+        glib::Continue(true)
+    }
+
+    // 按设置安排下次健康检查
+    fn schedule_health_check(&self) {
+        let settings = Data::new().get_settings();
+        self.watchdog.borrow_mut().check_at = if settings.health_check {
+            let secs = u64::from(settings.health_check_interval.max(10));
+            Some(Instant::now() + Duration::from_secs(secs))
+        } else {
+            None
+        };
+    }
+
+    // 通过本地端口请求测速地址, 结果通过 HealthCheck 返回
+    fn health_check(&self, node_id: u64) {
+        let db = Data::new();
+        let config = match db.get_config(node_id) {
+            Some(config) => config,
+            None => return,
+        };
+        let test_url = db.get_settings().test_url;
+        let sender = self.sender.clone();
+        smol::Task::spawn(async move {
+            let result = health_check(&config, &test_url, Duration::from_secs(10)).await;
+            let error = match result {
+                Ok(_) => None,
+                // 测速地址无效时无法判断, 视为正常
+                Err(SsrError::TestUrl(url)) => {
+                    logger::append(&format!("测速地址无效, 跳过健康检查: {}", url));
+                    None
+                }
+                Err(e) => Some(e.to_string()),
+            };
+            sender
+                .send(Action::HealthCheck(node_id, error))
+                .unwrap_or(());
+        })
+        .detach();
+    }
+
+    // 连续失败达到设定次数后切换到同组下一个可用节点
+    fn health_check_result(&self, node_id: u64, error: Option<String>) {
+        if self.watchdog.borrow().node_id != Some(node_id) {
+            return;
+        }
+        let settings = Data::new().get_settings();
+        let failures = {
+            let mut watchdog = self.watchdog.borrow_mut();
+            watchdog.failures = match &error {
+                Some(_) => watchdog.failures + 1,
+                None => 0,
+            };
+            watchdog.failures
+        };
+        if let Some(e) = error {
+            logger::append(&format!(
+                "健康检查失败 ({}/{}): {}",
+                failures, settings.health_check_failures, e
+            ));
+        }
+        if failures < settings.health_check_failures.max(1) {
+            self.schedule_health_check();
+            return;
+        }
+        let (group, _) = match Data::new().get_node(node_id) {
+            Some(node) => node,
+            None => return,
+        };
+        // 从当前节点的下一个开始依次测试, 选择第一个可以连通的节点
+        let index = group
+            .nodes
+            .iter()
+            .position(|node| node.id == node_id)
+            .unwrap_or(0);
+        let mut candidates = group.nodes;
+        candidates.rotate_left(index);
+        candidates.retain(|node| node.id != node_id);
+        // 与健康检查一样通过代理请求测速地址, 只能建立 TCP 连接的节点不算可用
+        let test_url = settings.test_url;
+        let sender = self.sender.clone();
+        smol::Task::spawn(async move {
+            for node in candidates {
+                if node_delay(&node.config, true, &test_url).await.is_ok() {
+                    sender
+                        .send(Action::FailOver(node_id, Some(node.id)))
+                        .unwrap_or(());
+                    return;
+                }
+            }
+            sender.send(Action::FailOver(node_id, None)).unwrap_or(());
+        })
+        .detach();
+    }
+
+    // 按设置安排下次自动选择节点, 当前节点所在分组未开启时取消
+    fn schedule_auto_select(&self) {
+        let db = Data::new();
+        let mut watchdog = self.watchdog.borrow_mut();
+        let auto_select = watchdog
+            .node_id
+            .and_then(|id| db.get_node(id))
+            .map(|(group, _)| group.auto_select)
+            .unwrap_or(false);
+        watchdog.select_at = if auto_select {
+            let minutes = u64::from(db.get_settings().auto_select_interval.max(1));
+            Some(Instant::now() + Duration::from_secs(minutes * 60))
+        } else {
+            None
+        };
+    }
+
+    // 测试分组全部节点的延迟, 完成后通过 AutoSelectResult 返回
+    fn auto_select(&self, group_id: u64) {
+        let db = Data::new();
+        let group = match db.get_group(group_id) {
+            Some(group) => group,
+            None => return,
+        };
+        let settings = db.get_settings();
+        let sender_clone = self.sender.clone();
+        smol::Task::spawn(async move {
+            let mut results = Vec::new();
+            stream::iter(group.nodes)
+                .map(|node| async move {
+                    let latency = Latency::new(&node_delay(&node.config, false, "").await);
+                    (node.id, latency)
+                })
+                .buffer_unordered(settings.speed_concurrency.max(1) as usize)
+                .for_each(|(node_id, latency)| {
+                    sender_clone
+                        .send(Action::SpeedResult(node_id, latency.clone()))
+                        .unwrap_or(());
+                    results.push((node_id, latency));
+                    future::ready(())
+                })
+                .await;
+            sender_clone
+                .send(Action::AutoSelectResult(group.id, results))
+                .unwrap_or(());
+        })
+        .detach();
+    }
+
+    // 连接延迟最低的节点, 当前节点的延迟高出不超过阈值时不切换
+    fn auto_select_result(&self, group_id: u64, results: &[(u64, Latency)]) {
+        let (current, selecting) = {
+            let watchdog = self.watchdog.borrow();
+            (watchdog.node_id, watchdog.selecting)
+        };
+        let best = results
+            .iter()
+            .filter_map(|(node_id, latency)| latency.ms.map(|ms| (*node_id, ms)))
+            .min_by_key(|(_, ms)| *ms);
+        // 读取所需数据后关闭数据库, connect 等调用会再次打开数据库
+        let (enabled, threshold, remarks) = {
+            let mut db = Data::new();
+            if db.set_latencies(results).is_none() {
+                logger::append("保存测速结果失败");
+            }
+            let enabled = matches!(
+                db.get_group(group_id),
+                Some(Group {
+                    auto_select: true,
+                    ..
+                })
+            );
+            let remarks = best
+                .and_then(|(best_id, _)| db.get_config(best_id))
+                .map(|config| config.remarks)
+                .unwrap_or_default();
+            (enabled, db.get_settings().auto_select_threshold, remarks)
+        };
+        // 等待期间已断开或关闭自动选择
+        if !enabled || (current.is_none() && !selecting) {
+            if selecting {
+                self.watchdog.replace(Watchdog::default());
+                self.header.set_connected(false);
+            }
+            return;
+        }
+        let (best_id, best_ms) = match best {
+            Some(best) => best,
+            None => {
+                logger::append("自动选择节点失败: 没有可用的节点");
+                if selecting {
+                    self.watchdog.replace(Watchdog::default());
+                    self.header.set_connected(false);
+                } else {
+                    self.schedule_auto_select();
+                }
+                self.sender
+                    .send(Action::ShowNotice(
+                        "自动选择节点失败: 没有可用的节点!".to_owned(),
+                    ))
+                    .unwrap_or(());
+                return;
+            }
+        };
+        let current_ms = results
+            .iter()
+            .find(|(node_id, _)| Some(*node_id) == current)
+            .and_then(|(_, latency)| latency.ms);
+        if let Some(ms) = current_ms {
+            if current == Some(best_id) || ms.saturating_sub(best_ms) <= threshold {
+                self.schedule_auto_select();
+                return;
+            }
+        }
+        logger::append(&format!("自动选择节点: {} ({} ms)", remarks, best_ms));
+        self.watchdog.replace(Watchdog {
+            node_id: Some(best_id),
+            ..Watchdog::default()
+        });
+        self.connect(best_id);
+        self.view.update_home_ssr_list_row_id(Some(best_id));
+        self.sender
+            .send(Action::ShowNotice(format!(
+                "已切换到 {} ({} ms)",
+                remarks, best_ms
+            )))
+            .unwrap_or(());
+    }
+
+    // 在主线程读取数据库, 找出到期的订阅后在后台依次下载, 结果通过 RefreshSub 返回
+    fn check_subs(&self, startup: bool) {
+        let now = timestamp();
+        let mut attempts = self.sub_attempts.borrow_mut();
+        let groups = Data::new()
+            .get_all()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|group| group.url.is_some() && group.update_interval > 0)
+            .filter(|group| {
+                let last = attempts
+                    .get(&group.id)
+                    .copied()
+                    .unwrap_or(0)
+                    .max(group.updated_at);
+                startup || now >= last + u64::from(group.update_interval) * 3600
+            })
+            .collect::<Vec<Group>>();
+        if groups.is_empty() {
+            return;
+        }
+        for group in &groups {
+            attempts.insert(group.id, now);
+        }
+        let sender = self.sender.clone();
+        smol::Task::spawn(async move {
+            for group in groups {
+                refresh_sub(group, &sender).await;
+            }
+        })
+        .detach();
+    }
+
+    // 测试当前分组全部节点的延迟, 每个节点的结果返回后立即更新
+    fn speed_test(&self, real: bool) {
+        let (gid, _) = self.view.get_home_gr_id();
+        let db = Data::new();
+        let group = match gid.and_then(|id| db.get_group(id)) {
+            Some(group) => group,
+            None => return,
+        };
+        let settings = db.get_settings();
+        let notice = if real {
+            "正在测试真实延迟..."
+        } else {
+            "正在测速..."
+        };
+        self.sender
+            .send(Action::ShowNotice(notice.to_owned()))
+            .unwrap_or(());
+        let sender_clone = self.sender.clone();
+        smol::Task::spawn(async move {
+            let test_url = settings.test_url;
+            let mut results = Vec::new();
+            stream::iter(group.nodes)
+                .map(|node| {
+                    let test_url = test_url.to_owned();
+                    async move {
+                        let result = node_delay(&node.config, real, &test_url).await;
+                        (node, result)
+                    }
+                })
+                .buffer_unordered(settings.speed_concurrency.max(1) as usize)
+                .for_each(|(node, result)| {
+                    if let Err(e) = &result {
+                        logger::append(&format!("{} 测速失败: {}", node.config.remarks, e));
+                    }
+                    let latency = Latency::new(&result);
+                    sender_clone
+                        .send(Action::SpeedResult(node.id, latency.clone()))
+                        .unwrap_or(());
+                    results.push((node.id, latency));
+                    future::ready(())
+                })
+                .await;
+            sender_clone
+                .send(Action::SpeedFinished(group.id, results))
+                .unwrap_or(());
+        })
+        .detach();
+    }
+
+    // 显示二维码识别结果
+    fn qrcode_result(&self, result: Option<(u64, usize, Vec<Group>)>) {
+        if let Some((group_id, count, configs)) = result {
+            self.view.update_home_sidebar(Some(group_id), &configs);
+            self.sender
+                .send(Action::ShowNotice(format!("成功添加 {} 个节点!", count)))
+                .unwrap_or(());
+        } else {
+            self.sender
+                .send(Action::ShowNotice("未识别到有效的二维码!".to_owned()))
+                .unwrap_or(());
+        }
+    }
+
     pub(crate) fn run() {
         let application = gtk::Application::new(
             Some("com.github.gmg137.ssr-gtk"),
@@ -350,3 +1177,48 @@ impl App {
         ApplicationExtManual::run(&application, &args);
     }
 }
+
+// 下载订阅, 结果通过 RefreshSub 返回
+async fn refresh_sub(group: Group, sender: &Sender<Action>) {
+    let url = match group.url {
+        Some(url) => url,
+        None => return,
+    };
+    match ssr_sub_url_parse(&url, &group.sub_options).await {
+        Ok((configs, info)) => sender
+            .send(Action::RefreshSub(group.id, configs, info))
+            .unwrap_or(()),
+        Err(e) => {
+            // 保留原有节点
+            logger::append(&format!("更新订阅 {} 失败: {}", group.name, e));
+            sender
+                .send(Action::ShowNotice(format!(
+                    "更新订阅 {} 失败: {}!",
+                    group.name, e
+                )))
+                .unwrap_or(());
+        }
+    }
+}
+
+// 测试单个节点的延迟, real 为 true 时通过临时 ssr-local 请求测速地址
+async fn node_delay(config: &SsrConfig, real: bool, test_url: &str) -> Result<u16, SsrError> {
+    let result = if real {
+        real_delay(config, test_url)
+            .timeout(Duration::from_secs(10))
+            .await
+    } else {
+        let host = config.remote_addr.to_owned();
+        let port = config.remote_port.to_owned();
+        timeout(host, port).timeout(Duration::from_secs(3)).await
+    };
+    result.unwrap_or(Err(SsrError::Timeout))
+}
+
+// 读取拖放或复制的图片文件
+fn read_image_uris(uris: &[glib::GString]) -> Vec<Vec<u8>> {
+    uris.iter()
+        .filter_map(|uri| glib::filename_from_uri(uri).ok())
+        .filter_map(|(path, _)| fs::read(path).ok())
+        .collect()
+}
@@ -5,7 +5,212 @@
 //
 
 use crate::{ssr::*, CONFIG_PATH};
+use serde::{Deserialize, Serialize};
 use sled::{Db, Error};
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// 数据结构版本, 结构变化时递增并在 migrate 中添加升级步骤
+const DB_VERSION: u32 = 1;
+
+// 分组
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Group {
+    // 唯一 ID
+    pub id: u64,
+    // 分组名
+    pub name: String,
+    // 订阅地址
+    pub url: Option<String>,
+    // 节点列表
+    pub nodes: Vec<Node>,
+    // 自动选择延迟最低的节点
+    #[serde(default)]
+    pub auto_select: bool,
+    // 订阅自动更新间隔 (小时), 0 为不自动更新
+    #[serde(default = "default_update_interval")]
+    pub update_interval: u32,
+    // 订阅最近一次更新成功的时间 (Unix 时间戳, 秒)
+    #[serde(default)]
+    pub updated_at: u64,
+    // 订阅下载选项
+    #[serde(default)]
+    pub sub_options: SubOptions,
+    // 订阅流量及到期信息
+    #[serde(default)]
+    pub sub_info: SubInfo,
+}
+
+// 订阅流量及到期信息
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SubInfo {
+    // 已上传流量 (字节)
+    pub upload: Option<u64>,
+    // 已下载流量 (字节)
+    pub download: Option<u64>,
+    // 总流量 (字节)
+    pub total: Option<u64>,
+    // 到期时间 (Unix 时间戳, 秒)
+    pub expire: Option<u64>,
+    // STATUS=/REMARKS= 行及信息节点的内容
+    pub notes: Vec<String>,
+}
+
+// 订阅下载选项
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SubOptions {
+    // 为空时使用默认 User-Agent
+    pub user_agent: String,
+    // 额外的请求头
+    pub headers: Vec<(String, String)>,
+    // 请求超时 (秒)
+    pub timeout: u32,
+    // 失败后重试次数
+    pub retries: u32,
+    // 通过当前连接的本地 SOCKS5 端口下载
+    pub use_proxy: bool,
+}
+
+impl Default for SubOptions {
+    fn default() -> Self {
+        SubOptions {
+            user_agent: String::new(),
+            headers: Vec::new(),
+            timeout: 30,
+            retries: 2,
+            use_proxy: false,
+        }
+    }
+}
+
+fn default_update_interval() -> u32 {
+    24
+}
+
+// 节点
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Node {
+    // 唯一 ID
+    pub id: u64,
+    // SSR 配置
+    pub config: SsrConfig,
+    // 最近一次测速结果
+    #[serde(default)]
+    pub latency: Option<Latency>,
+    // 最近一次更新订阅时的变化
+    #[serde(default)]
+    pub change: Option<NodeChange>,
+    // 手动修改过的节点, 更新订阅时保留修改
+    #[serde(default)]
+    pub edited: bool,
+}
+
+// 更新订阅时节点的变化
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum NodeChange {
+    Added,
+    Changed,
+    Removed,
+}
+
+// 更新订阅的结果统计
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SubDiff {
+    pub added: usize,
+    pub removed: usize,
+    pub changed: usize,
+}
+
+impl fmt::Display for SubDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "+{} / −{} / ~{}", self.added, self.removed, self.changed)
+    }
+}
+
+// 测速结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Latency {
+    // 延迟 (毫秒), 测试失败时为 None
+    pub ms: Option<u32>,
+    // 测试时间 (Unix 时间戳, 秒)
+    pub tested_at: u64,
+    // 失败原因
+    pub error: Option<String>,
+}
+
+impl Latency {
+    pub fn new(result: &Result<u16, SsrError>) -> Self {
+        let tested_at = timestamp();
+        match result {
+            Ok(ms) => Latency {
+                ms: Some(u32::from(*ms)),
+                tested_at,
+                error: None,
+            },
+            Err(e) => Latency {
+                ms: None,
+                tested_at,
+                error: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+// 偏好设置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    // 断线自动重连
+    pub auto_restart: bool,
+    // 最大重连次数
+    pub max_restarts: u32,
+    // 真实延迟测速地址
+    pub test_url: String,
+    // 同时测速的节点数
+    pub speed_concurrency: u32,
+    // 节点排序方式: default, latency, name, server
+    pub sort_by: String,
+    // 隐藏测速超时的节点
+    pub hide_timeout: bool,
+    // 自动选择节点的间隔 (分钟)
+    pub auto_select_interval: u32,
+    // 延迟改善超过该值 (毫秒) 才切换节点
+    pub auto_select_threshold: u32,
+    // 定时检查当前连接是否可用
+    pub health_check: bool,
+    // 健康检查间隔 (秒)
+    pub health_check_interval: u32,
+    // 连续失败该次数后切换节点
+    pub health_check_failures: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            auto_restart: true,
+            max_restarts: 5,
+            test_url: String::from("http://www.gstatic.com/generate_204"),
+            speed_concurrency: 8,
+            sort_by: String::from("default"),
+            hide_timeout: false,
+            auto_select_interval: 30,
+            auto_select_threshold: 50,
+            health_check: true,
+            health_check_interval: 60,
+            health_check_failures: 3,
+        }
+    }
+}
+
+// 当前 Unix 时间戳 (秒)
+pub fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
 
 pub struct Data {
     db: Result<Db, Error>,
@@ -13,9 +218,105 @@ pub struct Data {
 
 impl Data {
     pub fn new() -> Self {
-        Data {
+        let mut data = Data {
             db: sled::open(format!("{}/db", CONFIG_PATH.to_owned())),
+        };
+        data.migrate();
+        data
+    }
+
+    // 升级旧版本数据
+    fn migrate(&mut self) {
+        let db = match self.db.as_ref() {
+            Ok(db) => db,
+            Err(_) => return,
+        };
+        // 读取失败时不能当作旧版本处理, 否则会用旧数据覆盖现有分组
+        let version = match db.get(b"version") {
+            Ok(Some(v)) => match serde_json::from_slice::<u32>(&v) {
+                Ok(version) => version,
+                Err(_) => return,
+            },
+            Ok(None) => 0,
+            Err(_) => return,
+        };
+        if version >= DB_VERSION {
+            return;
         }
+        let mut batch = sled::Batch::default();
+        // v0: ssr_data 中保存 Vec<(分组名, 订阅地址, 配置列表)>, 旧数据保留以便回退
+        // 已有 groups 时说明之前已经转换过, 只补写版本号
+        if version < 1 {
+            type OldData = Vec<(String, Option<String>, Vec<SsrConfig>)>;
+            match (db.contains_key(b"groups"), db.get(b"ssr_data")) {
+                (Ok(true), _) => (),
+                (Ok(false), Ok(old)) => {
+                    // 旧数据无法解析时不写入版本号, 以免节点全部丢失且不再尝试升级
+                    let old = match old.map(|v| serde_json::from_slice::<OldData>(&v)) {
+                        Some(Ok(old)) => old,
+                        Some(Err(_)) => return,
+                        None => Vec::new(),
+                    };
+                    let mut groups = Vec::new();
+                    for (name, url, configs) in old {
+                        match self.new_group(name, url, configs) {
+                            Some(group) => groups.push(group),
+                            None => return,
+                        }
+                    }
+                    match serde_json::to_vec(&groups) {
+                        Ok(groups) => batch.insert("groups", groups),
+                        Err(_) => return,
+                    }
+                }
+                _ => return,
+            }
+        }
+        // 分组与版本号在同一批次中写入, 中途失败时不会只写入其中一个
+        if let Ok(version) = serde_json::to_vec(&DB_VERSION) {
+            batch.insert("version", version);
+            if db.apply_batch(batch).is_ok() {
+                db.flush().ok();
+            }
+        }
+    }
+
+    // 生成唯一 ID
+    fn generate_id(&self) -> Option<u64> {
+        self.db.as_ref().ok()?.generate_id().ok()
+    }
+
+    pub fn new_node(&self, config: SsrConfig) -> Option<Node> {
+        Some(Node {
+            id: self.generate_id()?,
+            config,
+            latency: None,
+            change: None,
+            edited: false,
+        })
+    }
+
+    pub fn new_group(
+        &self,
+        name: String,
+        url: Option<String>,
+        configs: Vec<SsrConfig>,
+    ) -> Option<Group> {
+        let mut nodes = Vec::new();
+        for config in configs {
+            nodes.push(self.new_node(config)?);
+        }
+        Some(Group {
+            id: self.generate_id()?,
+            name,
+            url,
+            nodes,
+            auto_select: false,
+            update_interval: default_update_interval(),
+            updated_at: timestamp(),
+            sub_options: SubOptions::default(),
+            sub_info: SubInfo::default(),
+        })
     }
 
     pub fn add_sub(
@@ -23,66 +324,391 @@ impl Data {
         url: String,
         group: String,
         configs: Vec<SsrConfig>,
-    ) -> Option<Vec<(String, Option<String>, Vec<SsrConfig>)>> {
-        if let Some(mut data) = self.get_all() {
-            data.push((group, Some(url), configs));
-            if self.set_all(&data).is_ok() {
-                return Some(data);
-            }
-        } else {
-            let data = vec![(group, Some(url), configs)];
-            if self.set_all(&data).is_ok() {
-                return Some(data);
-            }
-        }
-        None
+        options: SubOptions,
+        info: SubInfo,
+    ) -> Option<(u64, Vec<Group>)> {
+        let mut data = self.get_all().unwrap_or_default();
+        let mut group = self.new_group(group, Some(url), configs)?;
+        group.sub_options = options;
+        group.sub_info = info;
+        let group_id = group.id;
+        data.push(group);
+        self.set_all(&data).ok()?;
+        Some((group_id, data))
     }
 
-    pub fn add_ssr_url(
+    // 合并订阅分组的节点列表, 已有节点保留 ID、测速结果及本地设置
+    // keep 为正在使用的节点, 从订阅中移除时暂时保留并标记
+    pub fn update_sub(
         &mut self,
-        config: SsrConfig,
-    ) -> Option<(u8, Vec<(String, Option<String>, Vec<SsrConfig>)>)> {
-        if let Some(mut data) = self.get_all() {
-            let mut f = false;
-            let mut i = 0;
-            for (group, _, configs) in data.iter_mut() {
-                if group.eq(&"默认") {
-                    configs.push(config.to_owned());
-                    f = true;
-                    break;
+        group_id: u64,
+        configs: Vec<SsrConfig>,
+        info: SubInfo,
+        keep: Option<u64>,
+    ) -> Option<(SubDiff, Vec<Group>)> {
+        let mut data = self.get_all()?;
+        let group = data.iter_mut().find(|group| group.id == group_id)?;
+        let mut old = std::mem::take(&mut group.nodes);
+        let mut diff = SubDiff::default();
+        let mut nodes = Vec::new();
+        for config in configs {
+            // 优先匹配同一用户, 其次匹配同一服务器
+            let index = old
+                .iter()
+                .position(|node| same_identity(&node.config, &config))
+                .or_else(|| {
+                    old.iter()
+                        .position(|node| same_server(&node.config, &config))
+                });
+            let node = match index {
+                Some(index) => {
+                    let mut node = old.remove(index);
+                    node.change = None;
+                    let config = SsrConfig {
+                        local_addr: node.config.local_addr.to_owned(),
+                        local_port: node.config.local_port.to_owned(),
+                        timeout: node.config.timeout.to_owned(),
+                        group: node.config.group.to_owned(),
+                        ..config
+                    };
+                    if !node.edited && node.config != config {
+                        node.config = config;
+                        node.latency = None;
+                        node.change = Some(NodeChange::Changed);
+                        diff.changed += 1;
+                    }
+                    node
                 }
-                i += 1;
-            }
-            if !f {
-                data.push(("默认".to_owned(), None, vec![config]));
-            }
-            if self.set_all(&data).is_ok() {
-                return Some((i, data));
-            }
-        } else {
-            let data = vec![("默认".to_owned(), None, vec![config])];
-            if self.set_all(&data).is_ok() {
-                return Some((0, data));
+                None => {
+                    let mut node = self.new_node(config)?;
+                    node.change = Some(NodeChange::Added);
+                    diff.added += 1;
+                    node
+                }
+            };
+            nodes.push(node);
+        }
+        // 上次已标记为移除的节点不重复计数
+        diff.removed = old
+            .iter()
+            .filter(|node| node.change != Some(NodeChange::Removed))
+            .count();
+        if let Some(mut node) = old.into_iter().find(|node| Some(node.id) == keep) {
+            node.change = Some(NodeChange::Removed);
+            nodes.push(node);
+        }
+        group.nodes = nodes;
+        group.sub_info = info;
+        group.updated_at = timestamp();
+        self.set_all(&data).ok()?;
+        Some((diff, data))
+    }
+
+    pub fn add_ssr_url(&mut self, config: SsrConfig) -> Option<(u64, Vec<Group>)> {
+        self.add_to_group("默认", config)
+    }
+
+    pub fn add_config(&mut self, config: SsrConfig) -> Option<(u64, Vec<Group>)> {
+        let group = config.group.to_owned();
+        self.add_to_group(&group, config)
+    }
+
+    pub fn get_group(&self, group_id: u64) -> Option<Group> {
+        self.get_all()?
+            .into_iter()
+            .find(|group| group.id == group_id)
+    }
+
+    // 查找节点及其所在分组
+    pub fn get_node(&self, node_id: u64) -> Option<(Group, Node)> {
+        self.get_all()?.into_iter().find_map(|group| {
+            let node = group
+                .nodes
+                .iter()
+                .find(|node| node.id == node_id)?
+                .to_owned();
+            Some((group, node))
+        })
+    }
+
+    pub fn get_config(&self, node_id: u64) -> Option<SsrConfig> {
+        Some(self.get_node(node_id)?.1.config)
+    }
+
+    // 修改指定节点的配置, 返回节点所在分组 ID
+    pub fn edit_config(
+        &mut self,
+        node_id: u64,
+        mut config: SsrConfig,
+    ) -> Option<(u64, Vec<Group>)> {
+        let mut data = self.get_all()?;
+        let (group_id, node) = data.iter_mut().find_map(|group| {
+            let group_id = group.id;
+            let node = group.nodes.iter_mut().find(|node| node.id == node_id)?;
+            Some((group_id, node))
+        })?;
+        config.local_addr = node.config.local_addr.to_owned();
+        config.timeout = node.config.timeout.to_owned();
+        config.group = node.config.group.to_owned();
+        node.config = config;
+        node.edited = true;
+        self.set_all(&data).ok()?;
+        Some((group_id, data))
+    }
+
+    // 批量保存节点测速结果
+    pub fn set_latencies(&mut self, results: &[(u64, Latency)]) -> Option<Vec<Group>> {
+        let mut data = self.get_all()?;
+        for node in data.iter_mut().flat_map(|group| group.nodes.iter_mut()) {
+            if let Some((_, latency)) = results.iter().find(|(id, _)| *id == node.id) {
+                node.latency = Some(latency.to_owned());
             }
         }
-        None
+        self.set_all(&data).ok()?;
+        Some(data)
     }
 
-    pub fn get_all(&self) -> Option<Vec<(String, Option<String>, Vec<SsrConfig>)>> {
-        let ssr_data = self.db.as_ref().ok()?.get(b"ssr_data").ok()??;
-        serde_json::from_slice::<Vec<(String, Option<String>, Vec<SsrConfig>)>>(&ssr_data).ok()
+    // 开启或关闭分组的自动选择
+    pub fn set_auto_select(&mut self, group_id: u64, auto_select: bool) -> Option<Vec<Group>> {
+        let mut data = self.get_all()?;
+        data.iter_mut()
+            .find(|group| group.id == group_id)?
+            .auto_select = auto_select;
+        self.set_all(&data).ok()?;
+        Some(data)
     }
 
-    pub fn set_all(
+    // 修改订阅地址及下载选项
+    pub fn edit_sub(
         &mut self,
-        configs: &Vec<(String, Option<String>, Vec<SsrConfig>)>,
-    ) -> Result<(), Error> {
-        if let Ok(configs_vec) = serde_json::to_vec(&configs) {
-            if let Ok(db) = self.db.as_ref() {
-                db.insert(b"ssr_data", configs_vec)?;
-                db.flush()?;
+        group_id: u64,
+        url: String,
+        options: SubOptions,
+    ) -> Option<Vec<Group>> {
+        let mut data = self.get_all()?;
+        let group = data.iter_mut().find(|group| group.id == group_id)?;
+        group.url = Some(url);
+        group.sub_options = options;
+        self.set_all(&data).ok()?;
+        Some(data)
+    }
+
+    // 设置订阅自动更新间隔
+    pub fn set_update_interval(&mut self, group_id: u64, hours: u32) -> Option<Vec<Group>> {
+        let mut data = self.get_all()?;
+        data.iter_mut()
+            .find(|group| group.id == group_id)?
+            .update_interval = hours;
+        self.set_all(&data).ok()?;
+        Some(data)
+    }
+
+    pub fn remove_group(&mut self, group_id: u64) -> Option<Vec<Group>> {
+        let mut data = self.get_all()?;
+        data.retain(|group| group.id != group_id);
+        self.set_all(&data).ok()?;
+        Some(data)
+    }
+
+    // 删除节点, 返回节点所在分组 ID
+    pub fn remove_node(&mut self, node_id: u64) -> Option<(u64, Vec<Group>)> {
+        let mut data = self.get_all()?;
+        let group = data
+            .iter_mut()
+            .find(|group| group.nodes.iter().any(|node| node.id == node_id))?;
+        group.nodes.retain(|node| node.id != node_id);
+        let group_id = group.id;
+        self.set_all(&data).ok()?;
+        Some((group_id, data))
+    }
+
+    // 添加配置到指定分组, 分组不存在则新建
+    fn add_to_group(&mut self, group_name: &str, config: SsrConfig) -> Option<(u64, Vec<Group>)> {
+        let mut data = self.get_all().unwrap_or_default();
+        let group_id = match data.iter_mut().find(|group| group.name.eq(group_name)) {
+            Some(group) => {
+                group.nodes.push(self.new_node(config)?);
+                group.id
             }
+            None => {
+                let group = self.new_group(group_name.to_owned(), None, vec![config])?;
+                let group_id = group.id;
+                data.push(group);
+                group_id
+            }
+        };
+        self.set_all(&data).ok()?;
+        Some((group_id, data))
+    }
+
+    pub fn get_settings(&self) -> Settings {
+        self.db
+            .as_ref()
+            .ok()
+            .and_then(|db| db.get(b"settings").ok().flatten())
+            .and_then(|v| serde_json::from_slice::<Settings>(&v).ok())
+            .unwrap_or_default()
+    }
+
+    // 数据库未能打开 (如被其他进程锁定) 时返回错误, 不能当作保存成功
+    pub fn set_settings(&mut self, settings: &Settings) -> Result<(), Error> {
+        let db = self.db.as_ref().map_err(Error::clone)?;
+        if let Ok(settings_vec) = serde_json::to_vec(settings) {
+            db.insert(b"settings", settings_vec)?;
+            db.flush()?;
         }
         Ok(())
     }
+
+    pub fn get_all(&self) -> Option<Vec<Group>> {
+        let groups = self.db.as_ref().ok()?.get(b"groups").ok()??;
+        serde_json::from_slice::<Vec<Group>>(&groups).ok()
+    }
+
+    pub fn set_all(&mut self, groups: &[Group]) -> Result<(), Error> {
+        let db = self.db.as_ref().map_err(Error::clone)?;
+        if let Ok(groups_vec) = serde_json::to_vec(groups) {
+            db.insert(b"groups", groups_vec)?;
+            db.flush()?;
+        }
+        Ok(())
+    }
+}
+
+// 同一服务器上的同一用户
+fn same_identity(a: &SsrConfig, b: &SsrConfig) -> bool {
+    same_server(a, b) && a.password == b.password && a.protoparam == b.protoparam
+}
+
+fn same_server(a: &SsrConfig, b: &SsrConfig) -> bool {
+    a.remote_addr == b.remote_addr && a.remote_port == b.remote_port
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_data() -> Data {
+        Data {
+            db: sled::Config::new().temporary(true).open(),
+        }
+    }
+
+    fn legacy_fixture() -> Vec<u8> {
+        let configs = vec![
+            SsrConfig::default()
+                .set_remote_addr("1.2.3.4")
+                .set_remote_port("443")
+                .set_remarks("香港"),
+            SsrConfig::default()
+                .set_remote_addr("5.6.7.8")
+                .set_remote_port("8388")
+                .set_remarks("日本"),
+        ];
+        let old = vec![
+            ("默认".to_owned(), None, vec![configs[0].to_owned()]),
+            (
+                "机场".to_owned(),
+                Some("https://example.com/sub".to_owned()),
+                configs,
+            ),
+        ];
+        serde_json::to_vec(&old).unwrap()
+    }
+
+    fn version(data: &Data) -> Option<u32> {
+        let v = data.db.as_ref().unwrap().get(b"version").unwrap()?;
+        serde_json::from_slice(&v).ok()
+    }
+
+    #[test]
+    fn migrate_legacy_ssr_data() {
+        let mut data = temp_data();
+        let db = data.db.as_ref().unwrap();
+        db.insert(b"ssr_data", legacy_fixture()).unwrap();
+        data.migrate();
+
+        assert_eq!(version(&data), Some(DB_VERSION));
+        let groups = data.get_all().unwrap();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].name, "默认");
+        assert_eq!(groups[0].url, None);
+        assert_eq!(groups[0].nodes.len(), 1);
+        assert_eq!(groups[1].name, "机场");
+        assert_eq!(groups[1].url.as_deref(), Some("https://example.com/sub"));
+        let remarks = groups[1]
+            .nodes
+            .iter()
+            .map(|node| node.config.remarks.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(remarks, ["香港", "日本"]);
+        // ID 唯一
+        let mut ids = groups
+            .iter()
+            .flat_map(|group| group.nodes.iter().map(|node| node.id))
+            .chain(groups.iter().map(|group| group.id))
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), 5);
+        // 旧数据保留
+        assert!(data.db.as_ref().unwrap().contains_key(b"ssr_data").unwrap());
+    }
+
+    #[test]
+    fn migrate_keeps_existing_groups() {
+        let mut data = temp_data();
+        let group = data
+            .new_group("现有".to_owned(), None, vec![SsrConfig::default()])
+            .unwrap();
+        data.set_all(&[group]).unwrap();
+        data.db
+            .as_ref()
+            .unwrap()
+            .insert(b"ssr_data", legacy_fixture())
+            .unwrap();
+        data.migrate();
+
+        assert_eq!(version(&data), Some(DB_VERSION));
+        let groups = data.get_all().unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].name, "现有");
+    }
+
+    #[test]
+    fn migrate_runs_once() {
+        let mut data = temp_data();
+        data.db
+            .as_ref()
+            .unwrap()
+            .insert(b"ssr_data", legacy_fixture())
+            .unwrap();
+        data.migrate();
+        let groups = data.get_all().unwrap();
+        data.set_all(&groups[..1]).unwrap();
+        data.migrate();
+        assert_eq!(data.get_all().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn migrate_empty_db() {
+        let mut data = temp_data();
+        data.migrate();
+        assert_eq!(version(&data), Some(DB_VERSION));
+        assert_eq!(data.get_all().map(|groups| groups.len()), Some(0));
+    }
+
+    #[test]
+    fn migrate_skips_invalid_ssr_data() {
+        let mut data = temp_data();
+        data.db
+            .as_ref()
+            .unwrap()
+            .insert(b"ssr_data", &b"[[\"broken\""[..])
+            .unwrap();
+        data.migrate();
+        assert_eq!(version(&data), None);
+        assert!(data.get_all().is_none());
+        assert!(data.db.as_ref().unwrap().contains_key(b"ssr_data").unwrap());
+    }
 }
@@ -0,0 +1,121 @@
+//
+// logger.rs
+// Copyright (C) 2019 gmg137 <gmg137@live.com>
+// Distributed under terms of the GPLv3 license.
+//
+
+use crate::CONFIG_PATH;
+use lazy_static::lazy_static;
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+// 内存中保留的最大行数
+const MAX_LINES: usize = 1000;
+// 日志文件超过该大小时轮转
+const MAX_FILE_SIZE: u64 = 1024 * 1024;
+
+lazy_static! {
+    static ref LOG: Mutex<Log> = Mutex::new(Log::default());
+}
+
+#[derive(Default)]
+struct Log {
+    lines: VecDeque<String>,
+    // 每追加一行加一, 用于判断日志窗口是否需要刷新
+    version: u64,
+}
+
+// 日志文件路径
+pub fn log_path() -> String {
+    format!("{}/ssr-local.log", CONFIG_PATH.to_owned())
+}
+
+// 以追加方式打开日志文件, 用作 ssr-local 的 stdout/stderr
+pub fn open() -> io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path())
+}
+
+// 日志文件过大时轮转为 ssr-local.log.1, 需在 ssr-local 启动前调用
+pub fn rotate() {
+    if let Ok(meta) = fs::metadata(log_path()) {
+        if meta.len() > MAX_FILE_SIZE {
+            fs::rename(log_path(), format!("{}.1", log_path())).unwrap_or(());
+        }
+    }
+}
+
+// 写入一条本程序产生的日志
+pub fn append(line: &str) {
+    if let Ok(mut file) = open() {
+        writeln!(file, "[ssr-gtk] {}", line).unwrap_or(());
+    }
+}
+
+// 日志版本号
+pub fn version() -> u64 {
+    LOG.lock().map(|log| log.version).unwrap_or(0)
+}
+
+// 内存中的全部日志
+pub fn content() -> String {
+    LOG.lock()
+        .map(|log| {
+            log.lines
+                .iter()
+                .cloned()
+                .collect::<Vec<String>>()
+                .join("\n")
+        })
+        .unwrap_or_default()
+}
+
+// 后台读取日志文件新增的内容到内存
+pub fn watch() {
+    thread::spawn(|| {
+        let mut offset = 0;
+        loop {
+            offset = read_new_lines(offset);
+            thread::sleep(Duration::from_millis(500));
+        }
+    });
+}
+
+// 从 offset 处读取完整的新行, 返回新的 offset
+fn read_new_lines(offset: u64) -> u64 {
+    let mut file = match File::open(log_path()) {
+        Ok(file) => file,
+        Err(_) => return 0,
+    };
+    let len = file.metadata().map(|meta| meta.len()).unwrap_or(0);
+    // 文件被轮转后从头读取
+    let offset = if len < offset { 0 } else { offset };
+    if len == offset || file.seek(SeekFrom::Start(offset)).is_err() {
+        return offset;
+    }
+    let mut buffer = Vec::new();
+    if file.read_to_end(&mut buffer).is_err() {
+        return offset;
+    }
+    // 不完整的最后一行留到下次读取
+    let end = match buffer.iter().rposition(|b| *b == b'\n') {
+        Some(end) => end + 1,
+        None => return offset,
+    };
+    if let Ok(mut log) = LOG.lock() {
+        for line in String::from_utf8_lossy(&buffer[..end]).lines() {
+            log.lines.push_back(line.to_owned());
+            if log.lines.len() > MAX_LINES {
+                log.lines.pop_front();
+            }
+            log.version += 1;
+        }
+    }
+    offset + end as u64
+}
@@ -1,5 +1,6 @@
 mod app;
 mod db;
+mod logger;
 mod ssr;
 mod view;
 mod widgets;
@@ -62,6 +63,17 @@ lazy_static! {
         m.insert(13,"auth_chain_f");
         m
     };
+    // 混淆
+    static ref OBFS_LIST: HashMap<u8,&'static str>= {
+        let mut m = HashMap::new();
+        m.insert(0,"plain");
+        m.insert(1,"http_simple");
+        m.insert(2,"http_post");
+        m.insert(3,"http_mix");
+        m.insert(4,"tls1.2_ticket_auth");
+        m.insert(5,"tls1.2_ticket_fastauth");
+        m
+    };
 }
 
 #[macro_export]
@@ -97,6 +109,7 @@ macro_rules! upgrade_weak {
 
 fn main() {
     gtk::init().expect("Error initializing gtk.");
+    logger::watch();
 
     smol::run(async {
         App::run();
@@ -3,134 +3,625 @@
 // Copyright (C) 2019 gmg137 <gmg137@live.com>
 // Distributed under terms of the GPLv3 license.
 //
-use crate::db::Data;
+use crate::db::{Data, Group, SubInfo, SubOptions};
+use crate::logger;
 use async_net::TcpStream;
+use futures::io::{AsyncReadExt, AsyncWriteExt};
+use futures::lock::Mutex;
 use image::load_from_memory;
 use isahc::prelude::*;
+use lazy_static::lazy_static;
+use percent_encoding::percent_decode_str;
 use screenshot_rs::screenshot_area;
 use serde::{Deserialize, Serialize};
-use smol::io;
+use smol::{io, Timer};
+use smol_timeout::TimeoutExt;
 use std::{
-    borrow::Cow,
-    fs,
-    net::ToSocketAddrs,
+    fmt, fs,
+    io::Write,
+    net::{TcpListener, ToSocketAddrs},
+    os::unix::fs::OpenOptionsExt,
     path::Path,
-    process::{Command, Stdio},
-    time::Instant,
+    process::{Child, Command, Stdio},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
 };
 
+lazy_static! {
+    // 停止与启动 ssr-local 的任务依次执行, 避免交错时 PID 文件被覆盖
+    static ref PROCESS_LOCK: Mutex<()> = Mutex::new(());
+}
+
 // 扫码添加
-pub fn add_qrcode() -> Option<(u8, Vec<(String, Option<String>, Vec<SsrConfig>)>)> {
+pub fn add_qrcode() -> Option<(u64, usize, Vec<Group>)> {
     let image_path = format!("{}/qrcode.png", crate::CONFIG_PATH.to_owned());
     screenshot_area(image_path.to_owned(), true);
-    if let Ok(buffer) = fs::read(Path::new(&image_path)) {
-        fs::remove_file(image_path).unwrap_or(());
-        if let Ok(image) = load_from_memory(&buffer) {
-            let decoder = bardecoder::default_decoder();
-            let results = decoder.decode(&image);
-            for result in results {
-                if let Ok(ssr_str) = result {
-                    return add_ssr_url(ssr_str);
+    let buffer = fs::read(Path::new(&image_path)).ok()?;
+    fs::remove_file(image_path).unwrap_or(());
+    add_qrcode_images(&[buffer])
+}
+
+// 识别图片中的全部二维码并添加, 返回最后添加的分组及添加数量
+pub fn add_qrcode_images(images: &[Vec<u8>]) -> Option<(u64, usize, Vec<Group>)> {
+    let decoder = bardecoder::default_decoder();
+    let mut result = None;
+    let mut count = 0;
+    for buffer in images {
+        if let Ok(image) = load_from_memory(buffer) {
+            for text in decoder.decode(&image).into_iter().flatten() {
+                for url in text.lines() {
+                    if let Some((group_id, data)) = add_ssr_url(url.to_owned()) {
+                        result = Some((group_id, data));
+                        count += 1;
+                    }
                 }
             }
         }
     }
-    None
+    let (group_id, data) = result?;
+    Some((group_id, count, data))
 }
 
 // 添加订阅
-pub async fn add_sub(url: String) -> Option<Vec<(String, Option<String>, Vec<SsrConfig>)>> {
-    if url.starts_with("http://") || url.starts_with("https://") {
-        let configs = ssr_sub_url_parse(&url).await.ok()?;
-        let mut data = Data::new();
-        return data.add_sub(url, configs.get(0)?.group.to_owned(), configs);
+pub async fn add_sub(url: String, options: SubOptions) -> Result<(u64, Vec<Group>), SubError> {
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err(SubError::InvalidUrl(url));
     }
-    None
+    let (configs, info) = ssr_sub_url_parse(&url, &options).await?;
+    let group = configs.get(0).ok_or(SubError::NoNodes)?.group.to_owned();
+    let mut data = Data::new();
+    data.add_sub(url, group, configs, options, info)
+        .ok_or(SubError::Save)
 }
 
-// 添加 SSR 链接
-pub fn add_ssr_url(url: String) -> Option<(u8, Vec<(String, Option<String>, Vec<SsrConfig>)>)> {
-    let config = ssr_url_parse(url)?;
+// 添加 SSR/SS 链接
+pub fn add_ssr_url(url: String) -> Option<(u64, Vec<Group>)> {
+    let config = node_url_parse(url)?;
     let mut data = Data::new();
     data.add_ssr_url(config)
 }
 
-// 检测 ssr-local 是否运行
+// 添加手动配置
+pub fn add_config(config: SsrConfig) -> Option<(u64, Vec<Group>)> {
+    let mut data = Data::new();
+    data.add_config(config)
+}
+
+// 修改配置
+pub fn edit_config(node_id: u64, config: SsrConfig) -> Option<(u64, Vec<Group>)> {
+    let mut data = Data::new();
+    data.edit_config(node_id, config)
+}
+
+// 本程序启动的 ssr-local 的 PID 文件
+fn pid_path() -> String {
+    format!("{}/pid.txt", crate::CONFIG_PATH.to_owned())
+}
+
+// 检测指定 PID 是否为存活的 ssr-local 进程
+fn is_ssr_local(pid: u32) -> bool {
+    // /proc/<pid>/stat 格式: pid (comm) state ...
+    if let Ok(stat) = fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        if let (Some(start), Some(end)) = (stat.find('('), stat.rfind(')')) {
+            let state = stat[end + 1..].trim_start().chars().next();
+            return &stat[start + 1..end] == "ssr-local" && state != Some('Z');
+        }
+    }
+    false
+}
+
+// 获取由本程序启动的 ssr-local 进程 PID
+fn own_pid() -> Option<u32> {
+    let pid = fs::read_to_string(pid_path()).ok()?.trim().parse().ok()?;
+    if is_ssr_local(pid) {
+        Some(pid)
+    } else {
+        None
+    }
+}
+
+// 向进程发送信号
+fn kill(pid: u32, signal: &str) -> bool {
+    if let Ok(status) = Command::new("kill")
+        .arg(format!("-{}", signal))
+        .arg(pid.to_string())
+        .stderr(Stdio::null())
+        .status()
+    {
+        return status.success();
+    }
+    false
+}
+
+// 检测由本程序启动的 ssr-local 是否运行
 pub fn is_run() -> bool {
+    own_pid().is_some()
+}
+
+// 检测是否有其他程序启动的 ssr-local 在运行
+pub fn is_external_run() -> bool {
+    let own = own_pid();
     if let Ok(output) = Command::new("pidof").arg("ssr-local").output() {
-        return output.status.success() && !output.stdout.is_empty();
+        return String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .filter_map(|pid| pid.parse::<u32>().ok())
+            .any(|pid| Some(pid) != own);
     }
     false
 }
 
 // 测试延迟
-pub async fn timeout(host: String, port: String) -> io::Result<u16> {
+pub async fn timeout(host: String, port: String) -> Result<u16, SsrError> {
     let now = Instant::now();
-    let addrs = format!("{}:{}", host, port).to_socket_addrs()?;
+    let addrs = format!("{}:{}", host, port)
+        .to_socket_addrs()
+        .map_err(|_| SsrError::Resolve(host.to_owned()))?;
+    let mut error = SsrError::Resolve(host.to_owned());
     for addr in addrs {
-        let t = if TcpStream::connect(&addr).await.is_ok() {
-            now.elapsed().as_millis() as u16
-        } else {
-            9999
-        };
-        if t < 9999 {
-            return Ok(t);
+        match TcpStream::connect(&addr).await {
+            Ok(_) => return Ok(now.elapsed().as_millis() as u16),
+            Err(e) => error = SsrError::Connect(e),
         }
     }
-    Err(io::Error::last_os_error())
+    Err(error)
 }
 
-// 关闭 SSR 连接
-pub fn stop() -> bool {
-    if let Ok(status) = Command::new("killall").arg("ssr-local").status() {
-        return status.success();
+// 关闭 SSR 连接, 先发送 SIGTERM, 超时未退出则发送 SIGKILL
+// 等待进程退出最长约 3 秒, 需在后台任务中调用, 避免阻塞界面
+pub async fn stop() -> Result<(), SsrError> {
+    let _lock = PROCESS_LOCK.lock().await;
+    stop_locked().await
+}
+
+async fn stop_locked() -> Result<(), SsrError> {
+    let pid = match own_pid() {
+        Some(pid) => pid,
+        None => {
+            remove_run_files();
+            return Ok(());
+        }
+    };
+    kill(pid, "TERM");
+    for _ in 0..30 {
+        if !is_ssr_local(pid) {
+            remove_run_files();
+            return Ok(());
+        }
+        Timer::after(Duration::from_millis(100)).await;
     }
-    false
+    kill(pid, "KILL");
+    Timer::after(Duration::from_millis(100)).await;
+    if is_ssr_local(pid) {
+        return Err(SsrError::StopFailed(pid));
+    }
+    remove_run_files();
+    Ok(())
 }
 
-// 启动 SSR 连接
-pub fn run(config: &SsrConfig) -> bool {
-    if let Ok(addrs) = format!("{}:{}", config.remote_addr, config.remote_port).to_socket_addrs() {
-        for addr in addrs {
-            let remote_addr = addr.ip().to_string();
-            if let Ok(status) = Command::new("ssr-local")
-                .arg("-s")
-                .arg(remote_addr)
-                .arg("-p")
-                .arg(config.remote_port.to_owned())
-                .arg("-l")
-                .arg(config.local_port.to_owned())
-                .arg("-b")
-                .arg(config.local_addr.to_owned())
-                .arg("-k")
-                .arg(config.password.to_owned())
-                .arg("-m")
-                .arg(config.method.to_owned())
-                .arg("-t")
-                .arg(config.timeout.to_owned())
-                .arg("-o")
-                .arg(config.obfs.to_owned())
-                .arg("-g")
-                .arg(config.obfsparam.to_owned())
-                .arg("-O")
-                .arg(config.protocol.to_owned())
-                .arg("-G")
-                .arg(config.protoparam.to_owned())
-                .arg("-f")
-                .arg(format!("{}/pid.txt", crate::CONFIG_PATH.to_owned()))
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped())
-                .status()
-            {
-                return status.success();
+// 删除运行时生成的 PID 文件及配置文件
+fn remove_run_files() {
+    fs::remove_file(pid_path()).unwrap_or(());
+    fs::remove_file(config_path()).unwrap_or(());
+}
+
+// ssr-local 的配置文件, 运行期间保存密码, 断开后删除
+fn config_path() -> String {
+    format!("{}/ssr-local.json", crate::CONFIG_PATH.to_owned())
+}
+
+// 写入仅当前用户可读写 (0600) 的 ssr-local 配置文件
+fn write_config(path: &str, remote_addr: &str, config: &SsrConfig) -> io::Result<()> {
+    let parse_err = |_| io::Error::new(io::ErrorKind::InvalidInput, "端口或超时无效");
+    let json = serde_json::json!({
+        "server": remote_addr,
+        "server_port": config.remote_port.parse::<u16>().map_err(parse_err)?,
+        "local_address": config.local_addr,
+        "local_port": config.local_port.parse::<u16>().map_err(parse_err)?,
+        "password": config.password,
+        "method": config.method,
+        "timeout": config.timeout.parse::<u32>().map_err(parse_err)?,
+        "protocol": config.protocol,
+        "protocol_param": config.protoparam,
+        "obfs": config.obfs,
+        "obfs_param": config.obfsparam,
+    });
+    // 先删除旧文件, 确保新建文件使用 0600 权限
+    fs::remove_file(path).unwrap_or(());
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(json.to_string().as_bytes())
+}
+
+// 启动 SSR 连接, 需在后台任务中调用
+pub async fn run(config: &SsrConfig) -> Result<(), SsrError> {
+    config.check().map_err(SsrError::InvalidConfig)?;
+    let _lock = PROCESS_LOCK.lock().await;
+    // 确保上次启动的进程已退出, 避免 PID 文件被覆盖后无法关闭
+    stop_locked().await?;
+    let result = spawn(config);
+    if result.is_err() {
+        fs::remove_file(config_path()).unwrap_or(());
+    }
+    result
+}
+
+// 在前台启动 ssr-local, 输出写入日志文件
+fn spawn(config: &SsrConfig) -> Result<(), SsrError> {
+    let addr = format!("{}:{}", config.remote_addr, config.remote_port)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or_else(|| SsrError::Resolve(config.remote_addr.to_owned()))?;
+    // 检测本地端口是否被占用
+    let local = format!("{}:{}", config.local_addr, config.local_port);
+    if let Err(e) = TcpListener::bind(&local) {
+        if e.kind() == io::ErrorKind::AddrInUse {
+            return Err(SsrError::PortInUse(local));
+        }
+    }
+    write_config(&config_path(), &addr.ip().to_string(), config).map_err(SsrError::Config)?;
+    logger::rotate();
+    let stdout = logger::open().map_err(SsrError::Config)?;
+    let stderr = stdout.try_clone().map_err(SsrError::Config)?;
+    logger::append(&format!("启动 ssr-local: {}", config.remarks));
+    let mut child = Command::new("ssr-local")
+        .arg("-c")
+        .arg(config_path())
+        .stdin(Stdio::null())
+        .stdout(stdout)
+        .stderr(stderr)
+        .spawn()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => SsrError::NotInstalled,
+            _ => SsrError::Spawn(e),
+        })?;
+    fs::write(pid_path(), child.id().to_string()).unwrap_or(());
+    // 回收子进程并记录退出状态
+    let (status_sender, status_receiver) = mpsc::channel();
+    thread::spawn(move || {
+        if let Ok(status) = child.wait() {
+            logger::append(&format!("ssr-local 已退出: {}", status));
+            status_sender.send(status).unwrap_or(());
+        }
+    });
+    match status_receiver.recv_timeout(Duration::from_millis(300)) {
+        Ok(status) => Err(SsrError::Exited(status.code())),
+        Err(_) => Ok(()),
+    }
+}
+
+// 等待本地 SOCKS5 端口就绪
+pub async fn wait_ready(config: &SsrConfig, wait: Duration) -> Result<(), SsrError> {
+    wait_socks5(&local_connect_addr(config), wait, is_run).await
+}
+
+// 通过本地 SOCKS5 端口请求测速地址, 检查当前连接是否可用
+pub async fn health_check(
+    config: &SsrConfig,
+    test_url: &str,
+    wait: Duration,
+) -> Result<u16, SsrError> {
+    proxy_delay(&local_connect_addr(config), test_url, wait).await
+}
+
+// 等待 SOCKS5 端口响应握手, alive 返回 false 时表示进程已退出
+async fn wait_socks5(addr: &str, wait: Duration, alive: impl Fn() -> bool) -> Result<(), SsrError> {
+    let now = Instant::now();
+    loop {
+        if let Some(Ok(())) = socks5_greeting(addr).timeout(Duration::from_secs(1)).await {
+            return Ok(());
+        }
+        if !alive() {
+            return Err(SsrError::Stopped);
+        }
+        if now.elapsed() >= wait {
+            return Err(SsrError::NotReady(addr.to_owned()));
+        }
+        Timer::after(Duration::from_millis(200)).await;
+    }
+}
+
+// 本地监听地址, 监听全部地址时连接回环地址
+fn local_connect_addr(config: &SsrConfig) -> String {
+    match config.local_addr.as_str() {
+        "0.0.0.0" | "" => format!("127.0.0.1:{}", config.local_port),
+        "::" => format!("[::1]:{}", config.local_port),
+        addr if addr.contains(':') => format!("[{}]:{}", addr, config.local_port),
+        addr => format!("{}:{}", addr, config.local_port),
+    }
+}
+
+// 连接 SOCKS5 代理并完成无认证握手
+async fn socks5_connect(addr: &str) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(addr).await?;
+    stream.write_all(&[0x05, 0x01, 0x00]).await?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply == [0x05, 0x00] {
+        Ok(stream)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "SOCKS5 握手失败",
+        ))
+    }
+}
+
+// 发送 SOCKS5 无认证握手, 检查服务端应答
+async fn socks5_greeting(addr: &str) -> io::Result<()> {
+    socks5_connect(addr).await.map(|_| ())
+}
+
+// 通过 SOCKS5 代理连接目标主机
+async fn socks5_tunnel(proxy: &str, host: &str, port: u16) -> io::Result<TcpStream> {
+    if host.len() > 255 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "主机名过长"));
+    }
+    let mut stream = socks5_connect(proxy).await?;
+    // CONNECT, 目标地址使用域名类型, 由代理服务器解析
+    let mut request = vec![0x05, 0x01, 0x00, 0x03, host.len() as u8];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != 0x05 || reply[1] != 0x00 {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("SOCKS5 CONNECT 失败 (REP {})", reply[1]),
+        ));
+    }
+    // 跳过绑定地址及端口
+    let len = match reply[3] {
+        0x01 => 4,
+        0x04 => 16,
+        0x03 => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len).await?;
+            len[0] as usize
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "SOCKS5 应答无效",
+            ))
+        }
+    };
+    let mut bound = vec![0u8; len + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(stream)
+}
+
+// 通过 SOCKS5 代理请求测试地址, 返回从发起连接到收到 HTTP 响应行的耗时
+// 超过 wait 未收到响应时返回 Timeout, 状态码不是 2xx 时返回 HttpStatus
+pub async fn proxy_delay(proxy: &str, test_url: &str, wait: Duration) -> Result<u16, SsrError> {
+    let url = url::Url::parse(test_url).map_err(|_| SsrError::TestUrl(test_url.to_owned()))?;
+    let host = match (url.scheme(), url.host_str()) {
+        ("http", Some(host)) => host.to_owned(),
+        _ => return Err(SsrError::TestUrl(test_url.to_owned())),
+    };
+    let port = url.port().unwrap_or(80);
+    let path = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_owned(),
+    };
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}:{}\r\nUser-Agent: ssr-gtk\r\nConnection: close\r\n\r\n",
+        path, host, port
+    );
+    let now = Instant::now();
+    let target = host.trim_start_matches('[').trim_end_matches(']');
+    let status = http_status(proxy, target, port, &request)
+        .timeout(wait)
+        .await
+        .unwrap_or(Err(SsrError::Timeout))?;
+    if !(200..300).contains(&status) {
+        return Err(SsrError::HttpStatus(status));
+    }
+    Ok(now.elapsed().as_millis() as u16)
+}
+
+// 通过 SOCKS5 代理发送请求, 读取响应行中的状态码
+async fn http_status(proxy: &str, host: &str, port: u16, request: &str) -> Result<u16, SsrError> {
+    let invalid = || SsrError::Connect(io::Error::new(io::ErrorKind::InvalidData, "HTTP 响应无效"));
+    let mut stream = socks5_tunnel(proxy, host, port)
+        .await
+        .map_err(SsrError::Connect)?;
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .map_err(SsrError::Connect)?;
+    // 响应行: HTTP/1.1 204 No Content
+    let mut line = [0u8; 12];
+    stream
+        .read_exact(&mut line)
+        .await
+        .map_err(SsrError::Connect)?;
+    if !line.starts_with(b"HTTP/") || line[8] != b' ' {
+        return Err(invalid());
+    }
+    std::str::from_utf8(&line[9..12])
+        .ok()
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(invalid)
+}
+
+// 获取一个空闲的本地端口
+fn free_port() -> io::Result<u16> {
+    Ok(TcpListener::bind("127.0.0.1:0")?.local_addr()?.port())
+}
+
+// 测速用的临时 ssr-local, 离开作用域时结束进程并删除配置文件
+struct TestSsrLocal {
+    child: Child,
+    config_path: String,
+}
+
+impl Drop for TestSsrLocal {
+    fn drop(&mut self) {
+        self.child.kill().unwrap_or(());
+        self.child.wait().ok();
+        fs::remove_file(&self.config_path).unwrap_or(());
+    }
+}
+
+// 真实延迟: 在临时端口启动 ssr-local, 通过它请求测试地址
+pub async fn real_delay(config: &SsrConfig, test_url: &str) -> Result<u16, SsrError> {
+    let addr = format!("{}:{}", config.remote_addr, config.remote_port)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or_else(|| SsrError::Resolve(config.remote_addr.to_owned()))?;
+    let port = free_port().map_err(SsrError::Spawn)?;
+    let mut test_config = config.to_owned();
+    test_config.local_addr = String::from("127.0.0.1");
+    test_config.local_port = port.to_string();
+    let config_path = format!("{}/test-{}.json", crate::CONFIG_PATH.to_owned(), port);
+    write_config(&config_path, &addr.ip().to_string(), &test_config).map_err(SsrError::Config)?;
+    let child = Command::new("ssr-local")
+        .arg("-c")
+        .arg(&config_path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    let test = match child {
+        Ok(child) => TestSsrLocal { child, config_path },
+        Err(e) => {
+            fs::remove_file(&config_path).unwrap_or(());
+            return Err(match e.kind() {
+                io::ErrorKind::NotFound => SsrError::NotInstalled,
+                _ => SsrError::Spawn(e),
+            });
+        }
+    };
+    let proxy = format!("127.0.0.1:{}", port);
+    let pid = test.child.id();
+    wait_socks5(&proxy, Duration::from_secs(3), || is_ssr_local(pid)).await?;
+    proxy_delay(&proxy, test_url, Duration::from_secs(10)).await
+}
+
+// ssr-local 运行错误
+#[derive(Debug)]
+pub enum SsrError {
+    // 配置无效
+    InvalidConfig(String),
+    // 服务器地址解析失败
+    Resolve(String),
+    // 本地端口被占用
+    PortInUse(String),
+    // 写入配置或日志文件失败
+    Config(io::Error),
+    // 未安装 ssr-local
+    NotInstalled,
+    // 启动 ssr-local 失败
+    Spawn(io::Error),
+    // ssr-local 启动后退出, 被信号终止时退出码为 None
+    Exited(Option<i32>),
+    // ssr-local 已退出
+    Stopped,
+    // 本地端口未就绪
+    NotReady(String),
+    // 测速地址无效
+    TestUrl(String),
+    // 测速地址返回的状态码不是 2xx
+    HttpStatus(u16),
+    // 无法终止 ssr-local
+    StopFailed(u32),
+    // 连接超时
+    Timeout,
+    // 连接失败
+    Connect(io::Error),
+}
+
+impl fmt::Display for SsrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SsrError::InvalidConfig(e) => write!(f, "配置无效: {}", e),
+            SsrError::Resolve(host) => {
+                write!(f, "无法解析服务器地址 {}, 请检查网络或 DNS 设置!", host)
             }
+            SsrError::PortInUse(addr) => write!(
+                f,
+                "本地地址 {} 已被占用, 请关闭占用该端口的程序或修改本地端口!",
+                addr
+            ),
+            SsrError::Config(e) => write!(f, "写入配置文件失败: {}", e),
+            SsrError::NotInstalled => write!(f, "未找到 ssr-local, 请先安装并确保其位于 PATH 中!"),
+            SsrError::Spawn(e) => write!(f, "启动 ssr-local 失败: {}", e),
+            SsrError::Exited(Some(code)) => write!(
+                f,
+                "ssr-local 异常退出 (退出码 {}), 详情请查看运行日志!",
+                code
+            ),
+            SsrError::Exited(None) => write!(f, "ssr-local 被信号终止, 详情请查看运行日志!"),
+            SsrError::Stopped => write!(f, "ssr-local 已退出, 详情请查看运行日志!"),
+            SsrError::NotReady(addr) => write!(
+                f,
+                "本地端口 {} 未响应 SOCKS5 握手, 详情请查看运行日志!",
+                addr
+            ),
+            SsrError::TestUrl(url) => {
+                write!(f, "测速地址 {} 无效, 仅支持 http:// 地址!", url)
+            }
+            SsrError::HttpStatus(code) => write!(f, "测速地址返回 HTTP {}!", code),
+            SsrError::StopFailed(pid) => {
+                write!(f, "无法终止 ssr-local (PID {}), 请手动结束该进程!", pid)
+            }
+            SsrError::Timeout => write!(f, "连接超时!"),
+            SsrError::Connect(e) => write!(f, "连接失败: {}", e),
         }
     }
-    false
 }
 
+impl std::error::Error for SsrError {}
+
+// 订阅下载或解析错误
+#[derive(Debug)]
+pub enum SubError {
+    // 订阅地址无效
+    InvalidUrl(String),
+    // 请求头无效
+    Header(String),
+    // 下载失败
+    Fetch(isahc::Error),
+    // 服务器返回错误状态码
+    Status(u16),
+    // 读取内容失败
+    Read(io::Error),
+    // 内容不是 base64 编码
+    NotBase64,
+    // 没有有效的节点
+    NoNodes,
+    // 保存失败
+    Save,
+}
+
+impl fmt::Display for SubError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SubError::InvalidUrl(url) => {
+                write!(f, "订阅地址 {} 无效, 仅支持 http:// 及 https:// 地址", url)
+            }
+            SubError::Header(e) => write!(f, "请求头无效: {}", e),
+            SubError::Fetch(e) => write!(f, "下载订阅失败: {}", e),
+            SubError::Status(code) => write!(f, "服务器返回 HTTP {}", code),
+            SubError::Read(e) => write!(f, "读取订阅内容失败: {}", e),
+            SubError::NotBase64 => write!(f, "订阅内容不是有效的 base64 编码"),
+            SubError::NoNodes => write!(f, "订阅中没有有效的节点"),
+            SubError::Save => write!(f, "保存订阅失败"),
+        }
+    }
+}
+
+impl std::error::Error for SubError {}
+
 // SSR 配置信息
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SsrConfig {
     // 服务器地址
     pub remote_addr: String,
@@ -158,8 +649,6 @@ pub struct SsrConfig {
     pub remarks: String,
     // 分组
     pub group: String,
-    // 网络延迟
-    pub delay: String,
 }
 
 impl Default for SsrConfig {
@@ -178,11 +667,40 @@ impl Default for SsrConfig {
             obfsparam: String::new(),
             remarks: String::from("未命名"),
             group: String::from("默认"),
-            delay: String::from("0 ms"),
         }
     }
 }
 
+impl SsrConfig {
+    // 检查配置是否有效
+    pub fn check(&self) -> Result<(), String> {
+        if self.remote_addr.trim().is_empty() {
+            return Err("服务器地址不能为空!".to_owned());
+        }
+        match self.remote_port.trim().parse::<u16>() {
+            Ok(port) if port > 0 => (),
+            _ => return Err("远程端口无效!".to_owned()),
+        }
+        match self.local_port.trim().parse::<u16>() {
+            Ok(port) if port > 0 => (),
+            _ => return Err("本地端口无效!".to_owned()),
+        }
+        if self.password.is_empty() {
+            return Err("密码不能为空!".to_owned());
+        }
+        if self.method.is_empty() {
+            return Err("请选择加密方式!".to_owned());
+        }
+        if self.protocol.is_empty() {
+            return Err("请选择协议!".to_owned());
+        }
+        if self.obfs.is_empty() {
+            return Err("请选择混淆方式!".to_owned());
+        }
+        Ok(())
+    }
+}
+
 #[allow(dead_code)]
 impl SsrConfig {
     pub fn set_remote_addr(mut self, remote_addr: &str) -> Self {
@@ -237,101 +755,1011 @@ impl SsrConfig {
         self.group = group.to_owned();
         self
     }
-    pub fn set_delay(mut self, delay: u16) -> Self {
-        self.delay = format!("{} ms", delay);
-        self
-    }
 }
 
 // 解析 SSR 链接
+// 格式: ssr://base64(host:port:protocol:method:obfs:base64(password)/?obfsparam=..&remarks=..)
+// 从右往左切分, IPv6 地址中的冒号不影响解析
 pub fn ssr_url_parse(url: String) -> Option<SsrConfig> {
-    if url.starts_with("ssr://") {
-        let (_, ends) = url.split_at(6);
-        if let Ok(body) = base64::decode_config(ends, base64::URL_SAFE) {
-            let body = String::from_utf8_lossy(&body);
-            let body_vec = body.split(':').collect::<Vec<&str>>();
-            if body_vec.len() > 5 {
-                let body = format!("ssr://{}", body_vec[5]);
-                if let Ok(body) = url::Url::parse(&body) {
-                    let password = String::from_utf8_lossy(
-                        &base64::decode_config(&body.host_str().unwrap_or(""), base64::URL_SAFE)
-                            .unwrap_or_else(|_| vec![0u8]),
-                    )
-                    .to_string();
-                    let remote_addr = body_vec[0].to_string();
-                    let remote_port = body_vec[1].to_string();
-                    let protocol = body_vec[2].to_string();
-                    let method = body_vec[3].to_string();
-                    let obfs = body_vec[4].to_string();
-                    let mut obfsparam = "".to_string();
-                    let mut protoparam = "".to_string();
-                    let mut remarks = "".to_string();
-                    let mut group = "".to_string();
-                    for (k, v) in body.query_pairs() {
-                        match k {
-                            Cow::Borrowed("obfsparam") => obfsparam = v.to_string(),
-                            Cow::Borrowed("protoparam") => protoparam = v.to_string(),
-                            Cow::Borrowed("remarks") => remarks = v.to_string(),
-                            Cow::Borrowed("group") => group = v.to_string(),
-                            _ => (),
-                        };
-                    }
-                    obfsparam = String::from_utf8_lossy(
-                        &base64::decode_config(&obfsparam, base64::URL_SAFE)
-                            .unwrap_or_else(|_| vec![]),
-                    )
-                    .to_string();
-                    protoparam = String::from_utf8_lossy(
-                        &base64::decode_config(&protoparam, base64::URL_SAFE)
-                            .unwrap_or_else(|_| vec![]),
-                    )
-                    .to_string();
-                    remarks = String::from_utf8_lossy(
-                        &base64::decode_config(&remarks, base64::URL_SAFE)
-                            .unwrap_or_else(|_| "未命名".as_bytes().to_vec()),
-                    )
-                    .to_string();
-                    group = String::from_utf8_lossy(
-                        &base64::decode_config(&group, base64::URL_SAFE)
-                            .unwrap_or_else(|_| "默认".as_bytes().to_vec()),
-                    )
-                    .to_string();
-                    return Some(SsrConfig {
-                        remote_addr,
-                        remote_port,
-                        local_addr: "127.0.0.1".to_owned(),
-                        local_port: "1080".to_owned(),
-                        timeout: "300".to_owned(),
-                        protocol,
-                        method,
-                        obfs,
-                        obfsparam,
-                        protoparam,
-                        remarks,
-                        group,
-                        password,
-                        delay: String::from("0 ms"),
-                    });
+    let body = base64_decode(url.strip_prefix("ssr://")?)?;
+    let (main, query) = match body.find('/') {
+        Some(i) => (&body[..i], body[i + 1..].trim_start_matches('?')),
+        None => (body.as_str(), ""),
+    };
+    let mut parts = main.rsplitn(6, ':');
+    let password = base64_decode(parts.next()?)?;
+    let obfs = parts.next()?.to_owned();
+    let method = parts.next()?.to_owned();
+    let protocol = parts.next()?.to_owned();
+    let remote_port = parts.next()?.to_owned();
+    let remote_addr = parts
+        .next()?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_owned();
+    let mut obfsparam = String::new();
+    let mut protoparam = String::new();
+    let mut remarks = String::new();
+    let mut group = String::new();
+    // 参数值均为 base64, 不能按表单解码, 否则标准 base64 中的 + 会变成空格
+    for pair in query.split('&') {
+        let (k, v) = pair.split_at(pair.find('=').unwrap_or(pair.len()));
+        let v = v.get(1..).unwrap_or("");
+        match k {
+            "obfsparam" => obfsparam = base64_decode(v).unwrap_or_default(),
+            "protoparam" => protoparam = base64_decode(v).unwrap_or_default(),
+            "remarks" => remarks = base64_decode(v).unwrap_or_else(|| "未命名".to_owned()),
+            "group" => group = base64_decode(v).unwrap_or_else(|| "默认".to_owned()),
+            _ => (),
+        };
+    }
+    Some(SsrConfig {
+        remote_addr,
+        remote_port,
+        local_addr: "127.0.0.1".to_owned(),
+        local_port: "1080".to_owned(),
+        timeout: "300".to_owned(),
+        protocol,
+        method,
+        obfs,
+        obfsparam,
+        protoparam,
+        remarks,
+        group,
+        password,
+    })
+}
+
+// 生成 SSR 链接, 本地地址、本地端口和超时不属于链接内容
+pub fn ssr_url_encode(config: &SsrConfig) -> String {
+    let encode = |s: &str| base64::encode_config(s, base64::URL_SAFE_NO_PAD);
+    // IPv6 地址加上方括号
+    let remote_addr = if config.remote_addr.contains(':') {
+        format!("[{}]", config.remote_addr)
+    } else {
+        config.remote_addr.to_owned()
+    };
+    let body = format!(
+        "{}:{}:{}:{}:{}:{}/?obfsparam={}&protoparam={}&remarks={}&group={}",
+        remote_addr,
+        config.remote_port,
+        config.protocol,
+        config.method,
+        config.obfs,
+        encode(&config.password),
+        encode(&config.obfsparam),
+        encode(&config.protoparam),
+        encode(&config.remarks),
+        encode(&config.group),
+    );
+    format!("ssr://{}", encode(&body))
+}
+
+// 解析 SS 链接, 支持 SIP002 及旧版全 base64 格式
+pub fn ss_url_parse(url: String) -> Option<SsrConfig> {
+    if !url.starts_with("ss://") {
+        return None;
+    }
+    let (_, ends) = url.split_at(5);
+    let (body, tag) = match ends.find('#') {
+        Some(i) => (&ends[..i], Some(&ends[i + 1..])),
+        None => (ends, None),
+    };
+    let remarks = tag
+        .map(|tag| percent_decode_str(tag).decode_utf8_lossy().to_string())
+        .filter(|tag| !tag.is_empty())
+        .unwrap_or_else(|| "未命名".to_owned());
+    let config = if body.contains('@') {
+        // SIP002: ss://base64(method:password)@host:port/?plugin=xxx
+        let body = url::Url::parse(&format!("ss://{}", body)).ok()?;
+        let (method, password) = match body.password() {
+            Some(password) => (
+                percent_decode_str(body.username())
+                    .decode_utf8_lossy()
+                    .to_string(),
+                percent_decode_str(password).decode_utf8_lossy().to_string(),
+            ),
+            None => {
+                let userinfo =
+                    base64_decode(&percent_decode_str(body.username()).decode_utf8_lossy())?;
+                let (method, password) = userinfo.split_at(userinfo.find(':')?);
+                (method.to_owned(), password[1..].to_owned())
+            }
+        };
+        let remote_addr = match body.host()? {
+            url::Host::Ipv6(addr) => addr.to_string(),
+            host => host.to_string(),
+        };
+        let mut config = SsrConfig {
+            remote_addr,
+            remote_port: body.port()?.to_string(),
+            method,
+            password,
+            obfs: "plain".to_owned(),
+            ..SsrConfig::default()
+        };
+        for (k, v) in body.query_pairs() {
+            if k == "plugin" {
+                config = ss_plugin_parse(config, &v)?;
+            }
+        }
+        config
+    } else {
+        // 旧版: ss://base64(method:password@host:port)
+        let body = base64_decode(body)?;
+        let (userinfo, addr) = body.split_at(body.rfind('@')?);
+        let (method, password) = userinfo.split_at(userinfo.find(':')?);
+        let (host, port) = addr[1..].split_at(addr.rfind(':')? - 1);
+        SsrConfig {
+            remote_addr: host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_owned(),
+            remote_port: port[1..].parse::<u16>().ok()?.to_string(),
+            method: method.to_owned(),
+            password: password[1..].to_owned(),
+            obfs: "plain".to_owned(),
+            ..SsrConfig::default()
+        }
+    };
+    if config.remote_addr.is_empty() || config.method.is_empty() {
+        return None;
+    }
+    Some(SsrConfig {
+        protocol: "origin".to_owned(),
+        remarks,
+        ..config
+    })
+}
+
+// 解析 SIP003 插件参数, ssr-local 只兼容 simple-obfs
+fn ss_plugin_parse(config: SsrConfig, plugin: &str) -> Option<SsrConfig> {
+    let mut opts = plugin.split(';');
+    match opts.next()? {
+        "" => Some(config),
+        "obfs-local" | "simple-obfs" => {
+            let mut obfs = "plain".to_owned();
+            let mut obfsparam = String::new();
+            for opt in opts {
+                match opt.split_at(opt.find('=').unwrap_or_else(|| opt.len())) {
+                    ("obfs", "=http") => obfs = "http_simple".to_owned(),
+                    ("obfs", "=tls") => obfs = "tls1.2_ticket_auth".to_owned(),
+                    ("obfs-host", host) if !host.is_empty() => obfsparam = host[1..].to_owned(),
+                    _ => (),
                 }
             }
+            Some(SsrConfig {
+                obfs,
+                obfsparam,
+                ..config
+            })
+        }
+        _ => None,
+    }
+}
+
+// 解析 SSR/SS 链接
+pub fn node_url_parse(url: String) -> Option<SsrConfig> {
+    let url = url.trim().to_owned();
+    if url.starts_with("ssr://") {
+        ssr_url_parse(url)
+    } else {
+        ss_url_parse(url)
+    }
+}
+
+// 兼容 url-safe 与标准 base64 编码
+fn base64_decode(body: &str) -> Option<String> {
+    let body = body.trim().trim_end_matches('=');
+    let buf = base64::decode_config(body, base64::URL_SAFE_NO_PAD)
+        .or_else(|_| base64::decode_config(body, base64::STANDARD_NO_PAD))
+        .ok()?;
+    String::from_utf8(buf).ok()
+}
+
+// 下载订阅内容, 下载失败或服务器错误时按设置重试
+async fn fetch_sub(
+    url: &str,
+    options: &SubOptions,
+    proxy: Option<&str>,
+) -> Result<(String, Option<String>), SubError> {
+    let mut retries = 0;
+    loop {
+        let result = fetch_sub_once(url, options, proxy).await;
+        match &result {
+            Err(SubError::Fetch(_)) | Err(SubError::Status(500..=599))
+                if retries < options.retries =>
+            {
+                retries += 1;
+                Timer::after(Duration::from_secs(1)).await;
+            }
+            _ => return result,
+        }
+    }
+}
+
+async fn fetch_sub_once(
+    url: &str,
+    options: &SubOptions,
+    proxy: Option<&str>,
+) -> Result<(String, Option<String>), SubError> {
+    let mut builder =
+        Request::get(url).timeout(Duration::from_secs(u64::from(options.timeout.max(1))));
+    if let Some(proxy) = proxy {
+        // socks5h 由代理解析域名
+        builder = builder.proxy(
+            format!("socks5h://{}", proxy)
+                .parse::<isahc::http::Uri>()
+                .ok(),
+        );
+    }
+    if !options.user_agent.is_empty() {
+        builder = builder.header("User-Agent", options.user_agent.as_str());
+    }
+    for (name, value) in &options.headers {
+        builder = builder.header(name.as_str(), value.as_str());
+    }
+    let request = builder
+        .body(())
+        .map_err(|e| SubError::Header(e.to_string()))?;
+    let mut response = request.send_async().await.map_err(SubError::Fetch)?;
+    if !response.status().is_success() {
+        return Err(SubError::Status(response.status().as_u16()));
+    }
+    let userinfo = response
+        .headers()
+        .get("subscription-userinfo")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+    let body = response.text().map_err(SubError::Read)?;
+    Ok((body, userinfo))
+}
+
+// 当前运行的 ssr-local 的本地 SOCKS5 地址
+pub fn local_proxy() -> Option<String> {
+    if !is_run() {
+        return None;
+    }
+    let json = fs::read_to_string(config_path()).ok()?;
+    let json: serde_json::Value = serde_json::from_str(&json).ok()?;
+    let config = SsrConfig {
+        local_addr: json["local_address"].as_str()?.to_owned(),
+        local_port: json["local_port"].as_u64()?.to_string(),
+        ..SsrConfig::default()
+    };
+    Some(local_connect_addr(&config))
+}
+
+// 解析 SSR 定阅链接, 没有有效节点时返回错误以免清空原有节点
+// 开启 use_proxy 时通过当前连接下载, 否则直接下载失败后再尝试通过当前连接下载
+pub async fn ssr_sub_url_parse(
+    url: &str,
+    options: &SubOptions,
+) -> Result<(Vec<SsrConfig>, SubInfo), SubError> {
+    let proxy = local_proxy();
+    if options.use_proxy && proxy.is_some() {
+        return sub_parse(url, options, proxy.as_deref()).await;
+    }
+    match (sub_parse(url, options, None).await, proxy) {
+        (Err(e), Some(proxy)) => {
+            logger::append(&format!(
+                "直接下载订阅失败: {}, 尝试通过代理 {} 下载",
+                e, proxy
+            ));
+            sub_parse(url, options, Some(&proxy)).await
         }
+        (result, _) => result,
+    }
+}
+
+async fn sub_parse(
+    url: &str,
+    options: &SubOptions,
+    proxy: Option<&str>,
+) -> Result<(Vec<SsrConfig>, SubInfo), SubError> {
+    let (body, userinfo) = fetch_sub(url, options, proxy).await?;
+    let mut info = SubInfo::default();
+    if let Some(userinfo) = userinfo {
+        parse_userinfo(&userinfo, &mut info);
     }
-    None
+    let configs = parse_sub_body(&body, &mut info)?;
+    Ok((configs, info))
 }
 
-// 解析 SSR 定阅链接
-pub async fn ssr_sub_url_parse(url: &str) -> Result<Vec<SsrConfig>, isahc::Error> {
-    let body: String = isahc::get_async(url).await?.text()?;
-    let body = String::from_utf8_lossy(
-        &base64::decode_config(&body, base64::URL_SAFE).unwrap_or_else(|_| vec![]),
-    )
-    .to_string();
-    let body = body.split('\n').collect::<Vec<&str>>();
+// 解析订阅内容, STATUS=/REMARKS= 行及信息节点的内容写入 info
+fn parse_sub_body(body: &str, info: &mut SubInfo) -> Result<Vec<SsrConfig>, SubError> {
+    let body = body.split_whitespace().collect::<String>();
+    let body = base64_decode(&body).ok_or(SubError::NotBase64)?;
+    let mut remarks = InfoRemarks::default();
     let mut vec: Vec<SsrConfig> = Vec::new();
-    body.iter().for_each(|url| {
-        if let Some(config) = ssr_url_parse(url.to_string()) {
-            vec.push(config);
+    for line in body.split('\n') {
+        let line = line.trim();
+        if let Some(note) = line
+            .strip_prefix("STATUS=")
+            .or_else(|| line.strip_prefix("REMARKS="))
+        {
+            if !note.trim().is_empty() {
+                info.notes.push(note.trim().to_owned());
+            }
+            continue;
         }
-    });
+        match node_url_parse(line.to_string()) {
+            // 显示剩余流量、到期时间等信息的节点不可连接
+            Some(config) if remarks.parse(&config.remarks) => info.notes.push(config.remarks),
+            Some(config) => vec.push(config),
+            None => (),
+        }
+    }
+    remarks.merge(info);
+    if vec.is_empty() {
+        return Err(SubError::NoNodes);
+    }
     Ok(vec)
 }
+
+// 解析 subscription-userinfo 响应头, 如 upload=1; download=2; total=3; expire=4
+fn parse_userinfo(value: &str, info: &mut SubInfo) {
+    for item in value.split(';') {
+        let mut parts = item.splitn(2, '=');
+        let key = parts.next().map(str::trim);
+        let value = parts
+            .next()
+            .and_then(|value| value.trim().parse::<u64>().ok());
+        match (key, value) {
+            (Some("upload"), Some(value)) => info.upload = Some(value),
+            (Some("download"), Some(value)) => info.download = Some(value),
+            (Some("total"), Some(value)) => info.total = Some(value),
+            (Some("expire"), Some(value)) => info.expire = Some(value),
+            _ => (),
+        }
+    }
+}
+
+// 服务商信息节点中的流量及到期时间
+#[derive(Debug, Default, PartialEq)]
+struct InfoRemarks {
+    used: Option<u64>,
+    remaining: Option<u64>,
+    total: Option<u64>,
+    expire: Option<u64>,
+}
+
+impl InfoRemarks {
+    // 备注以 "剩余流量：10 GB"、"过期时间：2021-01-01"、"Traffic: 1GB/100GB" 等标签开头时
+    // 返回 true 并记录其中能解析的数值, 如 "过期时间：永久" 也是信息节点, 只是没有数值
+    // 只包含这些词的普通节点名不算信息节点
+    fn parse(&mut self, remarks: &str) -> bool {
+        let (label, value) = match info_label(remarks) {
+            Some(label) => label,
+            None => return false,
+        };
+        match label {
+            "剩余流量" => self.remaining = parse_size(value).or(self.remaining),
+            "已用流量" => self.used = parse_size(value).or(self.used),
+            "总流量" => self.total = parse_size(value).or(self.total),
+            "traffic" => {
+                let mut sizes = value.splitn(2, '/').map(parse_size);
+                if let (Some(Some(used)), Some(Some(total))) = (sizes.next(), sizes.next()) {
+                    self.used = Some(used);
+                    self.total = Some(total);
+                }
+            }
+            "过期时间" | "到期时间" | "套餐到期" | "expire" | "expire date" => {
+                self.expire = parse_date(value).or(self.expire)
+            }
+            // 没有数值的说明, 如官网地址、重置日期
+            _ => (),
+        }
+        true
+    }
+
+    // 填入 subscription-userinfo 响应头中没有的数值
+    fn merge(&self, info: &mut SubInfo) {
+        let total = self.total.or_else(|| Some(self.used? + self.remaining?));
+        let used = self
+            .used
+            .or_else(|| Some(total?.saturating_sub(self.remaining?)));
+        if info.total.is_none() {
+            if let (Some(total), Some(used)) = (total, used) {
+                info.total = Some(total);
+                info.upload = Some(0);
+                info.download = Some(used);
+            }
+        }
+        if info.expire.is_none() {
+            info.expire = self.expire;
+        }
+    }
+}
+
+// 拆分信息节点备注中的标签和值, 标签前可以有表情等符号, 标签后必须紧跟冒号
+fn info_label(remarks: &str) -> Option<(&'static str, &str)> {
+    const LABELS: [&str; 11] = [
+        "剩余流量",
+        "已用流量",
+        "总流量",
+        "过期时间",
+        "到期时间",
+        "套餐到期",
+        "流量重置",
+        "官网",
+        "traffic",
+        "expire date",
+        "expire",
+    ];
+    let remarks = remarks.trim_start_matches(|c: char| !c.is_alphanumeric());
+    LABELS.iter().find_map(|label| {
+        let head = remarks.get(..label.len())?;
+        if !head.eq_ignore_ascii_case(label) {
+            return None;
+        }
+        let rest = remarks[label.len()..].trim_start();
+        let value = rest.strip_prefix(':').or_else(|| rest.strip_prefix('：'))?;
+        Some((*label, value.trim()))
+    })
+}
+
+// 解析 "10.5 GB"、"100G"、"512MiB" 等流量, 按 1024 进制换算为字节
+fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number = number.parse::<f64>().ok()?;
+    let unit = unit.trim().to_ascii_uppercase();
+    let power = match unit.trim_end_matches("IB").trim_end_matches('B') {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        "P" => 5,
+        _ => return None,
+    };
+    Some((number * 1024f64.powi(power)) as u64)
+}
+
+// 解析 "2021-01-01"、"2021/1/1"、"2021.01.01 12:00" 等日期, 返回当天 0 点 (UTC) 的时间戳
+fn parse_date(value: &str) -> Option<u64> {
+    let date = value.split_whitespace().next()?;
+    let mut parts = date.split(&['-', '/', '.'][..]);
+    let year = parts.next()?.parse::<i64>().ok()?;
+    let month = parts.next()?.parse::<i64>().ok()?;
+    let day = parts.next()?.parse::<i64>().ok()?;
+    if parts.next().is_some()
+        || year < 1970
+        || !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+    {
+        return None;
+    }
+    // 公历日期到 1970-01-01 的天数
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * m + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    Some(days as u64 * 86400)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpStream as StdTcpStream;
+
+    fn node() -> SsrConfig {
+        SsrConfig {
+            remote_addr: "example.com".to_owned(),
+            remote_port: "8388".to_owned(),
+            method: "aes-256-cfb".to_owned(),
+            password: "password".to_owned(),
+            protocol: "auth_aes128_md5".to_owned(),
+            protoparam: "1234:abcd".to_owned(),
+            obfs: "tls1.2_ticket_auth".to_owned(),
+            obfsparam: "cloudflare.com".to_owned(),
+            remarks: "香港 01".to_owned(),
+            group: "分组".to_owned(),
+            ..SsrConfig::default()
+        }
+    }
+
+    // 本地地址、本地端口和超时不在链接中, 解析后保留原值再比较
+    fn round_trip(config: &SsrConfig) -> SsrConfig {
+        let parsed = ssr_url_parse(ssr_url_encode(config)).expect("parse failed");
+        SsrConfig {
+            local_addr: config.local_addr.to_owned(),
+            local_port: config.local_port.to_owned(),
+            timeout: config.timeout.to_owned(),
+            ..parsed
+        }
+    }
+
+    #[test]
+    fn ssr_url_round_trip() {
+        let config = node();
+        assert_eq!(round_trip(&config), config);
+    }
+
+    #[test]
+    fn ssr_url_round_trip_excludes_local_fields() {
+        let config = SsrConfig {
+            local_addr: "0.0.0.0".to_owned(),
+            local_port: "1081".to_owned(),
+            timeout: "60".to_owned(),
+            ..node()
+        };
+        let parsed = ssr_url_parse(ssr_url_encode(&config)).unwrap();
+        assert_eq!(parsed.local_addr, "127.0.0.1");
+        assert_eq!(parsed.local_port, "1080");
+        assert_eq!(parsed.timeout, "300");
+        assert_eq!(round_trip(&config), config);
+    }
+
+    #[test]
+    fn ssr_url_round_trip_non_ascii() {
+        let config = SsrConfig {
+            password: "密码:p@ss/word?=&".to_owned(),
+            protoparam: "用户:密钥".to_owned(),
+            obfsparam: "混淆.example.com,参数".to_owned(),
+            remarks: "🇯🇵 日本 東京 [IPLC] 倍率:1.5".to_owned(),
+            group: "机场 / グループ".to_owned(),
+            ..node()
+        };
+        assert_eq!(round_trip(&config), config);
+    }
+
+    #[test]
+    fn ssr_url_round_trip_padding() {
+        // 各种长度对应 base64 的不同填充
+        for len in 0..8 {
+            let text = "a".repeat(len);
+            let config = SsrConfig {
+                password: format!("p{}", text),
+                protoparam: text.to_owned(),
+                obfsparam: text.to_owned(),
+                remarks: format!("节点{}", text),
+                group: format!("g{}", text),
+                ..node()
+            };
+            assert_eq!(round_trip(&config), config, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn ssr_url_round_trip_ipv6() {
+        let config = SsrConfig {
+            remote_addr: "2001:db8::1".to_owned(),
+            ..node()
+        };
+        let url = ssr_url_encode(&config);
+        let body = base64_decode(url.trim_start_matches("ssr://")).unwrap();
+        assert!(body.starts_with("[2001:db8::1]:8388:"));
+        assert_eq!(round_trip(&config), config);
+    }
+
+    #[test]
+    fn ssr_url_parse_padded_link() {
+        // 兼容带填充的标准 base64 链接
+        let body = format!(
+            "1.2.3.4:443:origin:rc4-md5:plain:{}/?remarks={}",
+            base64::encode("pw"),
+            base64::encode("测试")
+        );
+        let config = ssr_url_parse(format!("ssr://{}", base64::encode(&body))).unwrap();
+        assert_eq!(config.remote_addr, "1.2.3.4");
+        assert_eq!(config.remote_port, "443");
+        assert_eq!(config.password, "pw");
+        assert_eq!(config.remarks, "测试");
+    }
+
+    // 只处理一个请求的 HTTP 服务, response 为 None 时不应答
+    fn http_server(response: Option<&'static str>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            match response {
+                Some(response) => reader.get_mut().write_all(response.as_bytes()).unwrap(),
+                None => thread::sleep(Duration::from_secs(2)),
+            }
+        });
+        port
+    }
+
+    // 最简单的 SOCKS5 服务, 只支持无认证的域名 CONNECT
+    fn socks5_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut client, _) = listener.accept().unwrap();
+            let mut greeting = [0u8; 3];
+            client.read_exact(&mut greeting).unwrap();
+            assert_eq!(greeting, [0x05, 0x01, 0x00]);
+            client.write_all(&[0x05, 0x00]).unwrap();
+            let mut header = [0u8; 5];
+            client.read_exact(&mut header).unwrap();
+            assert_eq!(header[..4], [0x05, 0x01, 0x00, 0x03]);
+            let mut host = vec![0u8; header[4] as usize];
+            client.read_exact(&mut host).unwrap();
+            let mut port = [0u8; 2];
+            client.read_exact(&mut port).unwrap();
+            let target = format!(
+                "{}:{}",
+                String::from_utf8(host).unwrap(),
+                u16::from_be_bytes(port)
+            );
+            let mut upstream = StdTcpStream::connect(target).unwrap();
+            client
+                .write_all(&[0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0, 0])
+                .unwrap();
+            let mut upstream_read = upstream.try_clone().unwrap();
+            let mut client_write = client.try_clone().unwrap();
+            thread::spawn(move || std::io::copy(&mut upstream_read, &mut client_write));
+            std::io::copy(&mut client, &mut upstream).ok();
+        });
+        addr
+    }
+
+    fn delay(response: Option<&'static str>, wait: Duration) -> Result<u16, SsrError> {
+        let url = format!("http://127.0.0.1:{}/generate_204", http_server(response));
+        futures::executor::block_on(proxy_delay(&socks5_server(), &url, wait))
+    }
+
+    #[test]
+    fn proxy_delay_ok() {
+        let result = delay(
+            Some("HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n"),
+            Duration::from_secs(5),
+        );
+        assert!(result.is_ok(), "{:?}", result);
+    }
+
+    #[test]
+    fn proxy_delay_status() {
+        let result = delay(
+            Some("HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n"),
+            Duration::from_secs(5),
+        );
+        assert!(
+            matches!(result, Err(SsrError::HttpStatus(503))),
+            "{:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn proxy_delay_timeout() {
+        let result = delay(None, Duration::from_millis(300));
+        assert!(matches!(result, Err(SsrError::Timeout)), "{:?}", result);
+    }
+
+    #[test]
+    fn proxy_delay_invalid_response() {
+        let result = delay(Some("SSH-2.0-OpenSSH_8.4\r\n"), Duration::from_secs(5));
+        assert!(matches!(result, Err(SsrError::Connect(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn proxy_delay_test_url() {
+        let result = futures::executor::block_on(proxy_delay(
+            "127.0.0.1:1",
+            "https://www.google.com/generate_204",
+            Duration::from_secs(1),
+        ));
+        assert!(matches!(result, Err(SsrError::TestUrl(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn parse_userinfo_header() {
+        let mut info = SubInfo::default();
+        parse_userinfo(
+            "upload=1234; download=5678;total=1073741824 ; expire=1735603200",
+            &mut info,
+        );
+        assert_eq!(info.upload, Some(1234));
+        assert_eq!(info.download, Some(5678));
+        assert_eq!(info.total, Some(1_073_741_824));
+        assert_eq!(info.expire, Some(1_735_603_200));
+    }
+
+    #[test]
+    fn parse_userinfo_ignores_invalid() {
+        let mut info = SubInfo::default();
+        parse_userinfo("upload=; download=abc; foo=1; total", &mut info);
+        assert_eq!(info.upload, None);
+        assert_eq!(info.download, None);
+        assert_eq!(info.total, None);
+        assert_eq!(info.expire, None);
+    }
+
+    #[test]
+    fn info_remarks_values() {
+        const GB: u64 = 1024 * 1024 * 1024;
+        let mut remarks = InfoRemarks::default();
+        assert!(remarks.parse("剩余流量：98.5 GB"));
+        assert!(remarks.parse("🚀 过期时间: 2024-12-31"));
+        assert_eq!(remarks.remaining, Some(GB * 985 / 10));
+        assert_eq!(remarks.expire, Some(1_735_603_200));
+
+        let mut remarks = InfoRemarks::default();
+        assert!(remarks.parse("Traffic: 10GB / 100GB"));
+        assert!(remarks.parse("Expire Date: 2024/1/1 08:00"));
+        assert_eq!(remarks.used, Some(10 * GB));
+        assert_eq!(remarks.total, Some(100 * GB));
+        assert_eq!(remarks.expire, Some(1_704_067_200));
+
+        let mut remarks = InfoRemarks::default();
+        assert!(remarks.parse("已用流量:512 MiB"));
+        assert!(remarks.parse("总流量：1T"));
+        assert_eq!(remarks.used, Some(512 * 1024 * 1024));
+        assert_eq!(remarks.total, Some(1024 * GB));
+    }
+
+    #[test]
+    fn info_remarks_notes() {
+        let mut remarks = InfoRemarks::default();
+        assert!(remarks.parse("官网：example.com"));
+        assert!(remarks.parse("流量重置: 每月 1 日"));
+        assert_eq!(remarks, InfoRemarks::default());
+    }
+
+    #[test]
+    fn info_remarks_without_values() {
+        // 标签匹配即为信息节点, 值无法解析时不记录数值
+        let mut remarks = InfoRemarks::default();
+        for name in &[
+            "剩余流量：未知",
+            "过期时间：永久",
+            "Traffic: 10GB",
+            "Expire: never",
+            "官网：",
+        ] {
+            assert!(remarks.parse(name), "{}", name);
+        }
+        assert_eq!(remarks, InfoRemarks::default());
+    }
+
+    #[test]
+    fn info_remarks_real_nodes() {
+        let mut remarks = InfoRemarks::default();
+        for name in &[
+            "香港 01",
+            "香港 到期前稳定",
+            "美国 Traffic 优化 02",
+            "Expire 专线",
+            "剩余流量节点",
+            "日本 官网同款",
+        ] {
+            assert!(!remarks.parse(name), "{}", name);
+        }
+        assert_eq!(remarks, InfoRemarks::default());
+    }
+
+    #[test]
+    fn info_remarks_merge() {
+        const GB: u64 = 1024 * 1024 * 1024;
+        let remarks = InfoRemarks {
+            remaining: Some(30 * GB),
+            total: Some(100 * GB),
+            expire: Some(1_735_603_200),
+            ..InfoRemarks::default()
+        };
+        let mut info = SubInfo::default();
+        remarks.merge(&mut info);
+        assert_eq!(info.total, Some(100 * GB));
+        assert_eq!(info.upload, Some(0));
+        assert_eq!(info.download, Some(70 * GB));
+        assert_eq!(info.expire, Some(1_735_603_200));
+
+        // 响应头中的数值优先
+        let mut info = SubInfo {
+            upload: Some(1),
+            download: Some(2),
+            total: Some(3),
+            expire: Some(4),
+            notes: Vec::new(),
+        };
+        remarks.merge(&mut info);
+        assert_eq!(
+            (info.upload, info.download, info.total),
+            (Some(1), Some(2), Some(3))
+        );
+        assert_eq!(info.expire, Some(4));
+
+        // 只有剩余流量时无法得出总量
+        let remarks = InfoRemarks {
+            remaining: Some(GB),
+            ..InfoRemarks::default()
+        };
+        let mut info = SubInfo::default();
+        remarks.merge(&mut info);
+        assert_eq!(info.total, None);
+    }
+
+    #[test]
+    fn sub_body_info_nodes() {
+        let link = |remarks: &str| ssr_url_encode(&node().set_remarks(remarks));
+        let body = [
+            "STATUS=剩余流量 10GB".to_owned(),
+            link("剩余流量：10 GB"),
+            link("过期时间：2024-12-31"),
+            link("剩余流量：未知"),
+            link("香港 01"),
+            link("美国 traffic 优化"),
+        ]
+        .join("\n");
+        let mut info = SubInfo::default();
+        let configs = parse_sub_body(&base64::encode(&body), &mut info).unwrap();
+        let remarks = configs
+            .iter()
+            .map(|config| config.remarks.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(remarks, ["香港 01", "美国 traffic 优化"]);
+        assert_eq!(
+            info.notes,
+            [
+                "剩余流量 10GB",
+                "剩余流量：10 GB",
+                "过期时间：2024-12-31",
+                "剩余流量：未知"
+            ]
+        );
+        assert_eq!(info.expire, Some(1_735_603_200));
+        assert_eq!(info.total, None);
+    }
+
+    #[test]
+    fn sub_body_errors() {
+        let mut info = SubInfo::default();
+        assert!(matches!(
+            parse_sub_body("<html>", &mut info),
+            Err(SubError::NotBase64)
+        ));
+        let body = base64::encode(ssr_url_encode(&node().set_remarks("官网：example.com")));
+        assert!(matches!(
+            parse_sub_body(&body, &mut info),
+            Err(SubError::NoNodes)
+        ));
+    }
+
+    fn ss_parse(url: &str) -> SsrConfig {
+        let config = node_url_parse(url.to_owned()).expect("parse failed");
+        // ss 节点可以作为 SSR 节点导出
+        assert_eq!(round_trip(&config), config);
+        config
+    }
+
+    #[test]
+    fn ss_url_sip002_base64_userinfo() {
+        let url = format!(
+            "ss://{}@1.2.3.4:8388#%E9%A6%99%E6%B8%AF%2001",
+            base64::encode_config("aes-256-gcm:pass:word", base64::URL_SAFE_NO_PAD)
+        );
+        let config = ss_parse(&url);
+        assert_eq!(config.remote_addr, "1.2.3.4");
+        assert_eq!(config.remote_port, "8388");
+        assert_eq!(config.method, "aes-256-gcm");
+        assert_eq!(config.password, "pass:word");
+        assert_eq!(config.protocol, "origin");
+        assert_eq!(config.obfs, "plain");
+        assert_eq!(config.remarks, "香港 01");
+    }
+
+    #[test]
+    fn ss_url_sip002_plain_userinfo() {
+        let config = ss_parse("ss://chacha20-ietf-poly1305:p%40ss@example.com:443");
+        assert_eq!(config.remote_addr, "example.com");
+        assert_eq!(config.remote_port, "443");
+        assert_eq!(config.method, "chacha20-ietf-poly1305");
+        assert_eq!(config.password, "p@ss");
+        assert_eq!(config.remarks, "未命名");
+    }
+
+    #[test]
+    fn ss_url_sip002_ipv6() {
+        let url = format!(
+            "ss://{}@[2001:db8::1]:8388/#v6",
+            base64::encode("aes-128-gcm:pw")
+        );
+        let config = ss_parse(&url);
+        assert_eq!(config.remote_addr, "2001:db8::1");
+        assert_eq!(config.remote_port, "8388");
+        assert_eq!(config.password, "pw");
+        assert_eq!(config.remarks, "v6");
+    }
+
+    #[test]
+    fn ss_url_simple_obfs_plugin() {
+        let userinfo = base64::encode_config("aes-256-cfb:pw", base64::URL_SAFE_NO_PAD);
+        let config = ss_parse(&format!(
+            "ss://{}@1.2.3.4:80/?plugin=obfs-local%3Bobfs%3Dhttp%3Bobfs-host%3Dcdn.example.com#http",
+            userinfo
+        ));
+        assert_eq!(config.obfs, "http_simple");
+        assert_eq!(config.obfsparam, "cdn.example.com");
+
+        let config = ss_parse(&format!(
+            "ss://{}@1.2.3.4:443/?plugin=simple-obfs;obfs=tls#tls",
+            userinfo
+        ));
+        assert_eq!(config.obfs, "tls1.2_ticket_auth");
+        assert_eq!(config.obfsparam, "");
+
+        let config = ss_parse(&format!("ss://{}@1.2.3.4:443/?plugin=#none", userinfo));
+        assert_eq!(config.obfs, "plain");
+    }
+
+    #[test]
+    fn ss_url_unsupported_plugin() {
+        let userinfo = base64::encode_config("aes-256-gcm:pw", base64::URL_SAFE_NO_PAD);
+        let url = format!(
+            "ss://{}@1.2.3.4:443/?plugin=v2ray-plugin%3Bmode%3Dwebsocket",
+            userinfo
+        );
+        assert_eq!(node_url_parse(url), None);
+    }
+
+    #[test]
+    fn ss_url_legacy() {
+        // 密码中含有 @ 和 :, 地址从右侧拆分
+        let url = format!(
+            "ss://{}#%E6%97%A7%E7%89%88",
+            base64::encode("rc4-md5:p@ss:word@example.com:8388")
+        );
+        let config = ss_parse(&url);
+        assert_eq!(config.remote_addr, "example.com");
+        assert_eq!(config.remote_port, "8388");
+        assert_eq!(config.method, "rc4-md5");
+        assert_eq!(config.password, "p@ss:word");
+        assert_eq!(config.obfs, "plain");
+        assert_eq!(config.remarks, "旧版");
+
+        // 不带填充的 url-safe 编码
+        let url = format!(
+            "ss://{}",
+            base64::encode_config("aes-128-cfb:pw@1.2.3.4:1", base64::URL_SAFE_NO_PAD)
+        );
+        let config = ss_parse(&url);
+        assert_eq!(config.remote_addr, "1.2.3.4");
+        assert_eq!(config.remote_port, "1");
+    }
+
+    #[test]
+    fn ss_url_legacy_ipv6() {
+        let url = format!(
+            "ss://{}",
+            base64::encode("aes-128-gcm:pw@[2001:db8::1]:8388")
+        );
+        let config = ss_parse(&url);
+        assert_eq!(config.remote_addr, "2001:db8::1");
+        assert_eq!(config.remote_port, "8388");
+        assert_eq!(config.password, "pw");
+    }
+
+    #[test]
+    fn ss_url_invalid() {
+        for body in &[
+            "aes-128-gcm:pw@1.2.3.4",
+            "aes-128-gcm:pw@1.2.3.4:port",
+            "aes-128-gcm:pw@1.2.3.4:70000",
+            "aes-128-gcmpw1.2.3.4:8388",
+        ] {
+            let url = format!("ss://{}", base64::encode(body));
+            assert_eq!(node_url_parse(url), None, "{}", body);
+        }
+        assert_eq!(node_url_parse("ss://@1.2.3.4:8388".to_owned()), None);
+        assert_eq!(node_url_parse("vmess://abc".to_owned()), None);
+    }
+}
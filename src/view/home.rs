@@ -4,21 +4,41 @@
 // Distributed under terms of the GPLv3 license.
 //
 
-use crate::{app::Action, ssr::SsrConfig};
+use crate::{
+    app::Action,
+    db::{timestamp, Group, Latency, Node, NodeChange, Settings, SubInfo},
+    upgrade_weak,
+};
 use crossbeam_channel::Sender;
 use gtk::prelude::*;
-use gtk::{Builder, Button, Label, ListBox, ListBoxRow};
+use gtk::{Builder, Button, CheckButton, ComboBoxText, Label, ListBox, ListBoxRow, SpinButton};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
 
 #[derive(Clone)]
 pub(crate) struct Home {
     sidebar: ListBox,
     group: Label,
+    sub_info: Label,
     refresh: Button,
     speed: Button,
+    real_speed: Button,
     remove: Button,
     ssr_listbox: ListBox,
-    group_index: Option<u8>,
-    ssr_index: Option<u8>,
+    sort_combo: ComboBoxText,
+    hide_timeout_check: CheckButton,
+    auto_select_check: CheckButton,
+    update_interval_box: gtk::Box,
+    update_interval_spin: SpinButton,
+    sub_setting: Button,
+    // 列表中各行对应的分组/节点 ID
+    group_ids: Rc<RefCell<Vec<u64>>>,
+    node_ids: Rc<RefCell<Vec<u64>>>,
+    // 各节点的延迟标签, 测速时逐个更新
+    delay_labels: Rc<RefCell<HashMap<u64, Label>>>,
+    group_id: Option<u64>,
+    node_id: Option<u64>,
     sender: Sender<Action>,
 }
 
@@ -30,28 +50,63 @@ impl Home {
         let group: Label = builder
             .get_object("group-name-label")
             .expect("无法获取 group-name-label .");
+        let sub_info: Label = builder
+            .get_object("sub-info-label")
+            .expect("无法获取 sub-info-label .");
         let refresh: Button = builder
             .get_object("refresh-button")
             .expect("无法获取 refresh-button .");
         let speed: Button = builder
             .get_object("speed-button")
             .expect("无法获取 speed-button .");
+        let real_speed: Button = builder
+            .get_object("real-speed-button")
+            .expect("无法获取 real-speed-button .");
         let remove: Button = builder
             .get_object("remove-button")
             .expect("无法获取 remove-button .");
         let ssr_listbox: ListBox = builder
             .get_object("ssr-listbox")
             .expect("无法获取 ssr-listbox .");
+        let sort_combo: ComboBoxText = builder
+            .get_object("sort-combo")
+            .expect("无法获取 sort-combo .");
+        let hide_timeout_check: CheckButton = builder
+            .get_object("hide-timeout-check")
+            .expect("无法获取 hide-timeout-check .");
+        let auto_select_check: CheckButton = builder
+            .get_object("auto-select-check")
+            .expect("无法获取 auto-select-check .");
+        let update_interval_box: gtk::Box = builder
+            .get_object("update-interval-box")
+            .expect("无法获取 update-interval-box .");
+        let update_interval_spin: SpinButton = builder
+            .get_object("update-interval-spin")
+            .expect("无法获取 update-interval-spin .");
+        let sub_setting: Button = builder
+            .get_object("sub-setting-button")
+            .expect("无法获取 sub-setting-button .");
 
         let s = Home {
             sidebar,
             group,
+            sub_info,
             refresh,
             speed,
+            real_speed,
             remove,
             ssr_listbox,
-            group_index: None,
-            ssr_index: None,
+            sort_combo,
+            hide_timeout_check,
+            auto_select_check,
+            update_interval_box,
+            update_interval_spin,
+            sub_setting,
+            group_ids: Rc::new(RefCell::new(Vec::new())),
+            node_ids: Rc::new(RefCell::new(Vec::new())),
+            delay_labels: Rc::new(RefCell::new(HashMap::new())),
+            group_id: None,
+            node_id: None,
             sender: sender.clone(),
         };
         Self::init(&s);
@@ -60,23 +115,27 @@ impl Home {
 
     fn init(s: &Self) {
         let sender = s.sender.clone();
+        let group_ids = s.group_ids.clone();
         s.sidebar.connect_row_selected(move |_, row| {
             if let Some(row) = row.as_ref() {
-                sender
-                    .send(Action::RefreshSsrListView(row.get_index() as u8))
-                    .unwrap_or(());
-                sender
-                    .send(Action::UpdateHomeSsrListRow(None))
-                    .unwrap_or(());
+                if let Some(id) = group_ids.borrow().get(row.get_index() as usize) {
+                    sender.send(Action::RefreshSsrListView(*id)).unwrap_or(());
+                    sender
+                        .send(Action::UpdateHomeSsrListRow(None))
+                        .unwrap_or(());
+                }
             }
         });
 
         let sender = s.sender.clone();
+        let node_ids = s.node_ids.clone();
         s.ssr_listbox.connect_row_selected(move |_, row| {
             if let Some(row) = row.as_ref() {
-                sender
-                    .send(Action::UpdateHomeSsrListRow(Some(row.get_index() as u8)))
-                    .unwrap_or(());
+                if let Some(id) = node_ids.borrow().get(row.get_index() as usize) {
+                    sender
+                        .send(Action::UpdateHomeSsrListRow(Some(*id)))
+                        .unwrap_or(());
+                }
             }
         });
 
@@ -90,33 +149,85 @@ impl Home {
             sender.send(Action::SpeedInt).unwrap_or(());
         });
 
+        let sender = s.sender.clone();
+        s.real_speed.connect_clicked(move |_| {
+            sender.send(Action::RealSpeedInt).unwrap_or(());
+        });
+
         let sender = s.sender.clone();
         s.remove.connect_clicked(move |_| {
             sender.send(Action::RemoveGroup).unwrap_or(());
         });
 
+        // 排序及过滤
+        let sender = s.sender.clone();
+        let check_weak = s.hide_timeout_check.downgrade();
+        s.sort_combo.connect_changed(move |combo| {
+            let check = upgrade_weak!(check_weak);
+            let sort_by = combo.get_active_id().map(|id| id.to_string());
+            sender
+                .send(Action::SortSsrList(
+                    sort_by.unwrap_or_default(),
+                    check.get_active(),
+                ))
+                .unwrap_or(());
+        });
+
+        let sender = s.sender.clone();
+        let combo_weak = s.sort_combo.downgrade();
+        s.hide_timeout_check.connect_toggled(move |check| {
+            let combo = upgrade_weak!(combo_weak);
+            let sort_by = combo.get_active_id().map(|id| id.to_string());
+            sender
+                .send(Action::SortSsrList(
+                    sort_by.unwrap_or_default(),
+                    check.get_active(),
+                ))
+                .unwrap_or(());
+        });
+
+        // 自动选择节点
+        let sender = s.sender.clone();
+        s.auto_select_check.connect_toggled(move |check| {
+            sender
+                .send(Action::SetAutoSelect(check.get_active()))
+                .unwrap_or(());
+        });
+
+        // 订阅自动更新间隔
+        let sender = s.sender.clone();
+        s.update_interval_spin.connect_value_changed(move |spin| {
+            sender
+                .send(Action::SetUpdateInterval(spin.get_value_as_int() as u32))
+                .unwrap_or(());
+        });
+
+        let sender = s.sender.clone();
+        s.sub_setting.connect_clicked(move |_| {
+            sender.send(Action::EditSubInit).unwrap_or(());
+        });
+
         s.sender.send(Action::RefreshHomeSidebar).unwrap_or(());
     }
 
-    pub(crate) fn select_group(&mut self, index: u8) {
-        self.group_index = Some(index);
+    pub(crate) fn select_group(&mut self, id: u64) {
+        self.group_id = Some(id);
     }
 
-    pub(crate) fn select_ssr(&mut self, index: Option<u8>) {
-        self.ssr_index = index;
+    pub(crate) fn select_ssr(&mut self, id: Option<u64>) {
+        self.node_id = id;
     }
 
-    pub(crate) fn update_sidebar(
-        &self,
-        group_id: u8,
-        group_list: &[(String, Option<String>, Vec<SsrConfig>)],
-    ) {
+    // 刷新分组列表并选中指定分组, 为 None 或分组不存在时选中第一个
+    pub(crate) fn update_sidebar(&self, group_id: Option<u64>, group_list: &[Group]) {
         self.sidebar.foreach(|w| {
             self.sidebar.remove(w);
         });
+        self.group_ids
+            .replace(group_list.iter().map(|group| group.id).collect());
 
-        group_list.iter().for_each(|(sl, _, _)| {
-            let label = Label::new(Some(sl));
+        group_list.iter().for_each(|group| {
+            let label = Label::new(Some(&group.name));
             label.set_halign(gtk::Align::Start);
             label.set_valign(gtk::Align::Fill);
             label.set_margin_start(18);
@@ -127,19 +238,48 @@ impl Home {
             row.add(&label);
             self.sidebar.insert(&row, -1);
         });
-        if let Some(one_row) = self.sidebar.get_row_at_index(group_id as i32) {
+        let index = group_id
+            .and_then(|id| group_list.iter().position(|group| group.id == id))
+            .unwrap_or(0);
+        if let Some(one_row) = self.sidebar.get_row_at_index(index as i32) {
             self.sidebar.select_row(Some(&one_row));
         }
         self.sidebar.show_all();
     }
 
-    pub(crate) fn update_ssr_list_view(&self, group_name: String, ssr_list: &[SsrConfig]) {
+    pub(crate) fn set_sort(&self, settings: &Settings) {
+        if !self.sort_combo.set_active_id(Some(&settings.sort_by)) {
+            self.sort_combo.set_active_id(Some("default"));
+        }
+        self.hide_timeout_check.set_active(settings.hide_timeout);
+    }
+
+    pub(crate) fn update_ssr_list_view(&self, group: &Group) {
         self.ssr_listbox.foreach(|w| {
             self.ssr_listbox.remove(w);
         });
 
-        let mut index = 0;
-        ssr_list.iter().for_each(|config| {
+        self.auto_select_check.set_active(group.auto_select);
+        self.update_interval_box.set_visible(group.url.is_some());
+        self.update_interval_spin
+            .set_value(f64::from(group.update_interval));
+        let sub_info = sub_info_text(&group.sub_info);
+        self.sub_info.set_text(&sub_info);
+        self.sub_info
+            .set_visible(group.url.is_some() && !sub_info.is_empty());
+        if group.sub_info.notes.is_empty() {
+            self.sub_info.set_tooltip_text(None);
+        } else {
+            self.sub_info
+                .set_tooltip_text(Some(&group.sub_info.notes.join("\n")));
+        }
+        let ssr_list = self.sort_nodes(&group.nodes);
+        self.node_ids
+            .replace(ssr_list.iter().map(|node| node.id).collect());
+        self.delay_labels.borrow_mut().clear();
+        ssr_list.iter().for_each(|node| {
+            let id = node.id;
+            let config = &node.config;
             let gtkbox = gtk::Box::new(gtk::Orientation::Horizontal, 4);
             gtkbox.set_margin_start(25);
             gtkbox.set_margin_end(25);
@@ -151,28 +291,68 @@ impl Home {
             remarks.set_max_width_chars(30);
             gtkbox.add(&remarks);
 
-            let delay = Label::new(Some(&config.delay));
+            // 最近一次更新订阅时的变化
+            let change = match node.change {
+                Some(NodeChange::Added) => Some("新增"),
+                Some(NodeChange::Changed) => Some("已变更"),
+                Some(NodeChange::Removed) => Some("已从订阅移除"),
+                None => None,
+            };
+            if let Some(change) = change {
+                let label = Label::new(Some(change));
+                label.get_style_context().add_class("dim-label");
+                gtkbox.add(&label);
+            }
+
+            let delay = Label::new(None);
+            set_latency_label(&delay, node.latency.as_ref());
             delay.set_hexpand(true);
             delay.set_halign(gtk::Align::End);
             delay.set_margin_end(30);
             gtkbox.add(&delay);
+            self.delay_labels.borrow_mut().insert(id, delay);
 
-            //let setting = Button::new_from_icon_name(
-            //Some("applications-system-symbolic"),
-            //gtk::IconSize::Button,
-            //);
-            //setting.set_margin_start(5);
-            //setting.set_margin_end(5);
-            //setting.set_margin_top(5);
-            //setting.set_margin_bottom(5);
-            //gtkbox.add(&setting);
+            let setting =
+                Button::from_icon_name(Some("applications-system-symbolic"), gtk::IconSize::Button);
+            setting.set_tooltip_text(Some("编辑"));
+            setting.set_margin_start(5);
+            setting.set_margin_top(5);
+            setting.set_margin_bottom(5);
+            let sender = self.sender.clone();
+            setting.connect_clicked(move |_| {
+                sender.send(Action::EditConfigInit(id)).unwrap_or(());
+            });
+            gtkbox.add(&setting);
+
+            let copy = Button::from_icon_name(Some("edit-copy-symbolic"), gtk::IconSize::Button);
+            copy.set_tooltip_text(Some("复制链接"));
+            copy.set_margin_start(5);
+            copy.set_margin_top(5);
+            copy.set_margin_bottom(5);
+            let sender = self.sender.clone();
+            copy.connect_clicked(move |_| {
+                sender.send(Action::CopySsrUrl(id)).unwrap_or(());
+            });
+            gtkbox.add(&copy);
+
+            let qrcode =
+                Button::from_icon_name(Some("insert-image-symbolic"), gtk::IconSize::Button);
+            qrcode.set_tooltip_text(Some("二维码"));
+            qrcode.set_margin_start(5);
+            qrcode.set_margin_top(5);
+            qrcode.set_margin_bottom(5);
+            let sender = self.sender.clone();
+            qrcode.connect_clicked(move |_| {
+                sender.send(Action::ShowQrcode(id)).unwrap_or(());
+            });
+            gtkbox.add(&qrcode);
             let remove = Button::from_icon_name(Some("user-trash-symbolic"), gtk::IconSize::Button);
             remove.set_margin_start(5);
             remove.set_margin_top(5);
             remove.set_margin_bottom(5);
             let sender = self.sender.clone();
             remove.connect_clicked(move |_| {
-                sender.send(Action::RemoveSSR).unwrap_or(());
+                sender.send(Action::RemoveSSR(id)).unwrap_or(());
             });
             gtkbox.add(&remove);
 
@@ -180,13 +360,102 @@ impl Home {
             row.add(&gtkbox);
 
             self.ssr_listbox.insert(&row, -1);
-            index += 1;
         });
-        self.group.set_text(&group_name);
+        self.group.set_text(&group.name);
         self.ssr_listbox.show_all();
     }
 
-    pub(crate) fn get_gr_id(&self) -> (Option<u8>, Option<u8>) {
-        (self.group_index, self.ssr_index)
+    pub(crate) fn update_ssr_latency(&self, node_id: u64, latency: &Latency) {
+        if let Some(label) = self.delay_labels.borrow().get(&node_id) {
+            set_latency_label(label, Some(latency));
+        }
+    }
+
+    // 按当前选项排序, 并过滤超时节点
+    fn sort_nodes<'a>(&self, ssr_list: &'a [Node]) -> Vec<&'a Node> {
+        let hide_timeout = self.hide_timeout_check.get_active();
+        let mut nodes = ssr_list
+            .iter()
+            .filter(|node| {
+                !(hide_timeout && matches!(node.latency, Some(Latency { ms: None, .. })))
+            })
+            .collect::<Vec<&Node>>();
+        match self.sort_combo.get_active_id().as_deref() {
+            // 可用节点按延迟升序, 其后为未测速及超时节点
+            Some("latency") => nodes.sort_by_key(|node| match &node.latency {
+                Some(Latency { ms: Some(ms), .. }) => (0, *ms),
+                None => (1, 0),
+                Some(_) => (2, 0),
+            }),
+            Some("name") => nodes.sort_by(|a, b| a.config.remarks.cmp(&b.config.remarks)),
+            Some("server") => nodes.sort_by_key(|node| {
+                (
+                    node.config.remote_addr.to_owned(),
+                    node.config.remote_port.parse::<u16>().unwrap_or(0),
+                )
+            }),
+            _ => (),
+        }
+        nodes
+    }
+
+    pub(crate) fn get_gr_id(&self) -> (Option<u64>, Option<u64>) {
+        (self.group_id, self.node_id)
+    }
+}
+
+// 显示测速结果, 提示中包含测试时间及失败原因
+fn set_latency_label(label: &Label, latency: Option<&Latency>) {
+    let latency = match latency {
+        Some(latency) => latency,
+        None => {
+            label.set_text("未测速");
+            label.set_tooltip_text(None);
+            return;
+        }
+    };
+    match latency.ms {
+        Some(ms) => label.set_text(&format!("{} ms", ms)),
+        None => label.set_text("超时"),
+    }
+    let elapsed = timestamp().saturating_sub(latency.tested_at);
+    let mut tooltip = match elapsed {
+        0..=59 => String::from("测试于刚刚"),
+        60..=3599 => format!("测试于 {} 分钟前", elapsed / 60),
+        3600..=86399 => format!("测试于 {} 小时前", elapsed / 3600),
+        _ => format!("测试于 {} 天前", elapsed / 86400),
+    };
+    if let Some(error) = &latency.error {
+        tooltip.push_str(&format!("\n{}", error));
+    }
+    label.set_tooltip_text(Some(&tooltip));
+}
+
+// 订阅的流量及到期时间, 没有这些信息时显示服务商的说明
+fn sub_info_text(info: &SubInfo) -> String {
+    let format_size = |size| {
+        glib::format_size(size)
+            .map(|size| size.to_string())
+            .unwrap_or_default()
+    };
+    let mut parts = Vec::new();
+    if let Some(total) = info.total {
+        let used = info.upload.unwrap_or(0) + info.download.unwrap_or(0);
+        parts.push(format!(
+            "已用 {} / {}",
+            format_size(used),
+            format_size(total)
+        ));
+    }
+    // 部分服务商用 0 表示不限期
+    if let Some(expire) = info.expire.filter(|expire| *expire > 0) {
+        if let Some(date) = glib::DateTime::from_unix_local(expire as i64).format("%Y-%m-%d") {
+            parts.push(format!("{} 到期", date));
+        }
+    }
+    if parts.is_empty() {
+        info.notes.join(" · ")
+    } else {
+        parts.join(" · ")
     }
 }
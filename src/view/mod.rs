@@ -6,7 +6,10 @@
 
 pub mod home;
 
-use crate::{app::Action, ssr::SsrConfig};
+use crate::{
+    app::Action,
+    db::{Group, Latency, Settings},
+};
 use crossbeam_channel::Sender;
 use gtk::Builder;
 use home::*;
@@ -36,32 +39,30 @@ impl View {
         })
     }
 
-    pub(crate) fn update_home_sidebar(
-        &self,
-        group_id: u8,
-        configs: &[(String, Option<String>, Vec<SsrConfig>)],
-    ) {
-        self.home.borrow_mut().update_sidebar(group_id, configs);
+    pub(crate) fn update_home_sidebar(&self, group_id: Option<u64>, groups: &[Group]) {
+        self.home.borrow_mut().update_sidebar(group_id, groups);
     }
 
-    pub(crate) fn update_home_ssr_list(
-        &self,
-        id: u8,
-        configs: &[(String, Option<String>, Vec<SsrConfig>)],
-    ) {
-        if let Some(config) = configs.get(id as usize) {
-            self.home
-                .borrow_mut()
-                .update_ssr_list_view(config.0.to_owned(), &config.2);
+    pub(crate) fn update_home_ssr_list(&self, group_id: u64, groups: &[Group]) {
+        if let Some(group) = groups.iter().find(|group| group.id == group_id) {
+            self.home.borrow_mut().update_ssr_list_view(group);
         }
-        self.home.borrow_mut().select_group(id);
+        self.home.borrow_mut().select_group(group_id);
     }
 
-    pub(crate) fn update_home_ssr_list_row_id(&self, id: Option<u8>) {
+    pub(crate) fn update_home_ssr_latency(&self, node_id: u64, latency: &Latency) {
+        self.home.borrow().update_ssr_latency(node_id, latency);
+    }
+
+    pub(crate) fn set_home_sort(&self, settings: &Settings) {
+        self.home.borrow().set_sort(settings);
+    }
+
+    pub(crate) fn update_home_ssr_list_row_id(&self, id: Option<u64>) {
         self.home.borrow_mut().select_ssr(id);
     }
 
-    pub(crate) fn get_home_gr_id(&self) -> (Option<u8>, Option<u8>) {
+    pub(crate) fn get_home_gr_id(&self) -> (Option<u64>, Option<u64>) {
         self.home.borrow_mut().get_gr_id()
     }
 }
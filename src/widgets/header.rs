@@ -5,12 +5,23 @@
 //
 
 use crate::app::Action;
-use crate::ssr::{is_run, stop, SsrConfig};
-use crate::APP_VERSION;
+use crate::db::{Data, Settings, SubOptions};
+use crate::logger;
+use crate::ssr::{is_external_run, is_run, ssr_url_encode, SsrConfig};
 use crate::{clone, upgrade_weak};
+use crate::{APP_VERSION, METHOD_LIST, OBFS_LIST, PROTOCOL_LIST};
 use crossbeam_channel::Sender;
+use gdk_pixbuf::{Colorspace, Pixbuf};
 use gtk::prelude::*;
-use gtk::{AboutDialog, Builder, Button, ComboBoxText, Dialog, Entry, RadioButton};
+use gtk::{
+    AboutDialog, Builder, Button, CheckButton, ComboBoxText, Dialog, Entry, FileChooserAction,
+    FileChooserNative, HeaderBar, Image, Label, RadioButton, ResponseType, SpinButton, Switch,
+    TextView,
+};
+use qrcode::{Color, QrCode};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fs;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
@@ -20,11 +31,19 @@ pub(crate) struct Header {
     disc_button: RadioButton,
     subscription_button: Button,
     subscription_dialog: SubDialog,
+    refresh_all_button: Button,
     add_url_button: Button,
     addurl_dialog: AddUrlDialog,
     manual_setting_button: Button,
     manual_setting_dialog: ManualSettingDialog,
     qrcode_button: Button,
+    qrcode_file_button: Button,
+    qrcode_paste_button: Button,
+    qrcode_dialog: QrcodeDialog,
+    log_button: Button,
+    log_dialog: LogDialog,
+    preferences_button: Button,
+    preferences_dialog: PreferencesDialog,
     about_button: Button,
     about_dialog: AboutDialog,
     sender: Sender<Action>,
@@ -36,6 +55,13 @@ pub(crate) struct SubDialog {
     dialog: Dialog,
     url_entry: Entry,
     sub_button: Button,
+    user_agent_entry: Entry,
+    headers_textview: TextView,
+    timeout_spin: SpinButton,
+    retries_spin: SpinButton,
+    proxy_check: CheckButton,
+    // 正在编辑的订阅分组, 为 None 时添加新订阅
+    edit_id: Rc<RefCell<Option<u64>>>,
 }
 
 #[derive(Clone)]
@@ -61,6 +87,42 @@ pub(crate) struct ManualSettingDialog {
     protoparam_entry: Entry,
     obfs_combo: ComboBoxText,
     obfsparam_entry: Entry,
+    sub_warning_label: Label,
+    // 正在编辑的节点 ID, 为 None 时表示新增
+    edit_id: Rc<RefCell<Option<u64>>>,
+}
+
+#[derive(Clone)]
+pub(crate) struct QrcodeDialog {
+    dialog: Dialog,
+    headerbar: HeaderBar,
+    image: Image,
+    save_button: Button,
+    pixbuf: Rc<RefCell<Option<Pixbuf>>>,
+}
+
+#[derive(Clone)]
+pub(crate) struct LogDialog {
+    dialog: Dialog,
+    textview: TextView,
+    copy_button: Button,
+    save_button: Button,
+}
+
+#[derive(Clone)]
+pub(crate) struct PreferencesDialog {
+    dialog: Dialog,
+    cancel_button: Button,
+    finished_button: Button,
+    auto_restart_switch: Switch,
+    max_restarts_spin: SpinButton,
+    test_url_entry: Entry,
+    speed_concurrency_spin: SpinButton,
+    auto_select_interval_spin: SpinButton,
+    auto_select_threshold_spin: SpinButton,
+    health_check_switch: Switch,
+    health_check_interval_spin: SpinButton,
+    health_check_failures_spin: SpinButton,
 }
 
 impl Header {
@@ -78,6 +140,9 @@ impl Header {
         let subscription_button: Button = builder
             .get_object("subscription-button")
             .expect("Couldn't get subscription button");
+        let refresh_all_button: Button = builder
+            .get_object("refresh-all-button")
+            .expect("Couldn't get refresh all button");
         let dialog: Dialog = builder
             .get_object("subscription-dialog")
             .expect("Couldn't get sub dialog");
@@ -87,10 +152,31 @@ impl Header {
         let sub_button: Button = builder
             .get_object("sub-button")
             .expect("Couldn't get sub button");
+        let user_agent_entry: Entry = builder
+            .get_object("sub-user-agent-entry")
+            .expect("Couldn't get sub-user-agent-entry");
+        let headers_textview: TextView = builder
+            .get_object("sub-headers-textview")
+            .expect("Couldn't get sub-headers-textview");
+        let timeout_spin: SpinButton = builder
+            .get_object("sub-timeout-spin")
+            .expect("Couldn't get sub-timeout-spin");
+        let retries_spin: SpinButton = builder
+            .get_object("sub-retries-spin")
+            .expect("Couldn't get sub-retries-spin");
+        let proxy_check: CheckButton = builder
+            .get_object("sub-proxy-check")
+            .expect("Couldn't get sub-proxy-check");
         let subscription_dialog = SubDialog {
             dialog,
             url_entry,
             sub_button,
+            user_agent_entry,
+            headers_textview,
+            timeout_spin,
+            retries_spin,
+            proxy_check,
+            edit_id: Rc::new(RefCell::new(None)),
         };
         let add_url_button: Button = builder
             .get_object("add-url-button")
@@ -154,6 +240,9 @@ impl Header {
         let obfsparam_entry: Entry = builder
             .get_object("obfsparam-entry")
             .expect("Couldn't get obfsparam_entry");
+        let sub_warning_label: Label = builder
+            .get_object("sub-warning-label")
+            .expect("Couldn't get sub_warning_label");
         let manual_setting_dialog = ManualSettingDialog {
             dialog,
             configname_entry,
@@ -169,10 +258,111 @@ impl Header {
             protoparam_entry,
             obfs_combo,
             obfsparam_entry,
+            sub_warning_label,
+            edit_id: Rc::new(RefCell::new(None)),
         };
         let qrcode_button: Button = builder
             .get_object("qrcode-button")
             .expect("Couldn't get qrcode_button");
+        let qrcode_file_button: Button = builder
+            .get_object("qrcode-file-button")
+            .expect("Couldn't get qrcode_file_button");
+        let qrcode_paste_button: Button = builder
+            .get_object("qrcode-paste-button")
+            .expect("Couldn't get qrcode_paste_button");
+        let dialog: Dialog = builder
+            .get_object("qrcode-dialog")
+            .expect("Couldn't get qrcode-dialog");
+        let headerbar: HeaderBar = builder
+            .get_object("qrcode-headerbar")
+            .expect("Couldn't get qrcode-headerbar");
+        let image: Image = builder
+            .get_object("qrcode-image")
+            .expect("Couldn't get qrcode-image");
+        let save_button: Button = builder
+            .get_object("save-qrcode-button")
+            .expect("Couldn't get save-qrcode-button");
+        let qrcode_dialog = QrcodeDialog {
+            dialog,
+            headerbar,
+            image,
+            save_button,
+            pixbuf: Rc::new(RefCell::new(None)),
+        };
+        let log_button: Button = builder
+            .get_object("log-button")
+            .expect("Couldn't get log_button");
+        let dialog: Dialog = builder
+            .get_object("log-dialog")
+            .expect("Couldn't get log-dialog");
+        let textview: TextView = builder
+            .get_object("log-textview")
+            .expect("Couldn't get log-textview");
+        let copy_button: Button = builder
+            .get_object("copy-log-button")
+            .expect("Couldn't get copy-log-button");
+        let save_button: Button = builder
+            .get_object("save-log-button")
+            .expect("Couldn't get save-log-button");
+        let log_dialog = LogDialog {
+            dialog,
+            textview,
+            copy_button,
+            save_button,
+        };
+        let preferences_button: Button = builder
+            .get_object("preferences-button")
+            .expect("Couldn't get preferences_button");
+        let dialog: Dialog = builder
+            .get_object("preferences-dialog")
+            .expect("Couldn't get preferences-dialog");
+        let cancel_button: Button = builder
+            .get_object("pref-cancel-button")
+            .expect("Couldn't get pref-cancel-button");
+        let finished_button: Button = builder
+            .get_object("pref-finished-button")
+            .expect("Couldn't get pref-finished-button");
+        let auto_restart_switch: Switch = builder
+            .get_object("auto-restart-switch")
+            .expect("Couldn't get auto-restart-switch");
+        let max_restarts_spin: SpinButton = builder
+            .get_object("max-restarts-spin")
+            .expect("Couldn't get max-restarts-spin");
+        let test_url_entry: Entry = builder
+            .get_object("test-url-entry")
+            .expect("Couldn't get test-url-entry");
+        let speed_concurrency_spin: SpinButton = builder
+            .get_object("speed-concurrency-spin")
+            .expect("Couldn't get speed-concurrency-spin");
+        let auto_select_interval_spin: SpinButton = builder
+            .get_object("auto-select-interval-spin")
+            .expect("Couldn't get auto-select-interval-spin");
+        let auto_select_threshold_spin: SpinButton = builder
+            .get_object("auto-select-threshold-spin")
+            .expect("Couldn't get auto-select-threshold-spin");
+        let health_check_switch: Switch = builder
+            .get_object("health-check-switch")
+            .expect("Couldn't get health-check-switch");
+        let health_check_interval_spin: SpinButton = builder
+            .get_object("health-check-interval-spin")
+            .expect("Couldn't get health-check-interval-spin");
+        let health_check_failures_spin: SpinButton = builder
+            .get_object("health-check-failures-spin")
+            .expect("Couldn't get health-check-failures-spin");
+        let preferences_dialog = PreferencesDialog {
+            dialog,
+            cancel_button,
+            finished_button,
+            auto_restart_switch,
+            max_restarts_spin,
+            test_url_entry,
+            speed_concurrency_spin,
+            auto_select_interval_spin,
+            auto_select_threshold_spin,
+            health_check_switch,
+            health_check_interval_spin,
+            health_check_failures_spin,
+        };
         let about_button: Button = builder
             .get_object("about-button")
             .expect("Couldn't get about_button");
@@ -184,11 +374,19 @@ impl Header {
             disc_button,
             subscription_button,
             subscription_dialog,
+            refresh_all_button,
             add_url_button,
             addurl_dialog,
             manual_setting_button,
             manual_setting_dialog,
             qrcode_button,
+            qrcode_file_button,
+            qrcode_paste_button,
+            qrcode_dialog,
+            log_button,
+            log_dialog,
+            preferences_button,
+            preferences_dialog,
             about_button,
             about_dialog,
             sender: sender.clone(),
@@ -227,6 +425,13 @@ impl Header {
         // 初始化连接按钮
         if is_run() {
             s.conn_button.set_active(true);
+            s.conn_button.set_label("已连接");
+        } else if is_external_run() {
+            sender
+                .send(Action::ShowNotice(
+                    "检测到由其他程序启动的 ssr-local, 本程序不会关闭它!".to_owned(),
+                ))
+                .unwrap_or(());
         }
 
         // 连接断开按钮行为
@@ -237,18 +442,21 @@ impl Header {
             if button.get_active(){
                 sender_clone.send(Action::ConnectSSR).unwrap();
             } else {
-                stop();
+                button.set_label("连接");
+                button.set_sensitive(true);
+                sender_clone.send(Action::DisconnectSSR).unwrap_or(());
             }
         }));
 
         // 订阅
-        let dialog_weak = s.subscription_dialog.dialog.downgrade();
-        s.subscription_button
-            .connect_clicked(clone!(dialog_weak=>move|_| {
-                let dialog = upgrade_weak!(dialog_weak);
-                dialog.run();
-                dialog.hide();
-            }));
+        let subscription_dialog = s.subscription_dialog.clone();
+        s.subscription_button.connect_clicked(move |_| {
+            subscription_dialog.edit_id.replace(None);
+            subscription_dialog.fill("", &SubOptions::default());
+            subscription_dialog.sub_button.set_label("订阅");
+            subscription_dialog.dialog.run();
+            subscription_dialog.dialog.hide();
+        });
 
         // 订阅输入框激活
         let sub_button_weak = s.subscription_dialog.sub_button.downgrade();
@@ -261,18 +469,25 @@ impl Header {
 
         // 订阅按钮
         let sender_clone = sender.clone();
-        let dialog_weak = s.subscription_dialog.dialog.downgrade();
-        let entry_weak = s.subscription_dialog.url_entry.downgrade();
+        let subscription_dialog = s.subscription_dialog.clone();
+        s.subscription_dialog.sub_button.connect_clicked(move |_| {
+            let dialog = &subscription_dialog;
+            let url = dialog.url_entry.get_text().trim().to_owned();
+            if !url.is_empty() {
+                let options = dialog.read();
+                let action = match *dialog.edit_id.borrow() {
+                    Some(group_id) => Action::EditSub(group_id, url, options),
+                    None => Action::SubscriptionInit(url, options),
+                };
+                sender_clone.send(action).unwrap_or(());
+            }
+            dialog.dialog.hide();
+        });
+
+        // 关闭订阅窗口时隐藏
         s.subscription_dialog
-            .sub_button
-            .connect_clicked(clone!(dialog_weak,entry_weak=>move|_| {
-                let dialog = upgrade_weak!(dialog_weak);
-                let url = upgrade_weak!(entry_weak).get_text();
-                if !url.is_empty(){
-                    sender_clone.send(Action::SubscriptionInit(url.to_owned())).unwrap_or(());
-                }
-                dialog.hide();
-            }));
+            .dialog
+            .connect_delete_event(|dialog, _| dialog.hide_on_delete());
 
         // 通过URL添加
         let dialog_weak = s.addurl_dialog.dialog.downgrade();
@@ -308,14 +523,19 @@ impl Header {
             }));
 
         // 手动添加
-        let dialog_weak = s.manual_setting_dialog.dialog.downgrade();
+        let setting_dialog = s.manual_setting_dialog.clone();
         s.manual_setting_button
-            .connect_clicked(clone!(dialog_weak=>move|_| {
-                let dialog = upgrade_weak!(dialog_weak);
-                dialog.run();
-                dialog.hide();
+            .connect_clicked(clone!(setting_dialog=>move|_| {
+                setting_dialog.clear();
+                setting_dialog.dialog.run();
+                setting_dialog.dialog.hide();
             }));
 
+        // 关闭手动设置窗口时隐藏
+        s.manual_setting_dialog
+            .dialog
+            .connect_delete_event(|dialog, _| dialog.hide_on_delete());
+
         // 取消添加
         let dialog_weak = s.manual_setting_dialog.dialog.downgrade();
         s.manual_setting_dialog
@@ -338,6 +558,7 @@ impl Header {
         let protoparam_weak = s.manual_setting_dialog.protoparam_entry.downgrade();
         let obfs_weak = s.manual_setting_dialog.obfs_combo.downgrade();
         let obfsparam_weak = s.manual_setting_dialog.obfsparam_entry.downgrade();
+        let edit_id = s.manual_setting_dialog.edit_id.clone();
         let sender_clone = sender.clone();
         s.manual_setting_dialog.finished_button.connect_clicked(
             clone!(dialog_weak,group_weak,configname_weak,
@@ -363,9 +584,15 @@ impl Header {
                     None => String::new()
                 };
                 let obfsparam = upgrade_weak!(obfsparam_weak).get_text().to_string();
-                let remarks = upgrade_weak!(configname_weak).get_text().to_string();
-                let group = upgrade_weak!(group_weak).get_text().to_string();
-                sender_clone.send(Action::AddConfig(SsrConfig{
+                let mut remarks = upgrade_weak!(configname_weak).get_text().trim().to_string();
+                if remarks.is_empty() {
+                    remarks = "未命名".to_owned();
+                }
+                let mut group = upgrade_weak!(group_weak).get_text().trim().to_string();
+                if group.is_empty() {
+                    group = "默认".to_owned();
+                }
+                let config = SsrConfig{
                     local_addr: "127.0.0.1".to_owned(),
                     timeout: "300".to_owned(),
                     group,
@@ -376,22 +603,180 @@ impl Header {
                     protocol,
                     password,
                     method,
-                    local_port,
-                    remote_port,
-                    remote_addr,
-                    delay: String::from("0 ms")
-                })).unwrap_or(());
+                    local_port: local_port.trim().to_owned(),
+                    remote_port: remote_port.trim().to_owned(),
+                    remote_addr: remote_addr.trim().to_owned(),
+                };
+                // 配置有误时保留对话框, 方便修改
+                if let Err(e) = config.check() {
+                    sender_clone.send(Action::ShowNotice(e)).unwrap_or(());
+                    return;
+                }
+                if let Some(node_id) = edit_id.borrow_mut().take() {
+                    sender_clone.send(Action::EditConfig(node_id, config)).unwrap_or(());
+                } else {
+                    sender_clone.send(Action::AddConfig(config)).unwrap_or(());
+                }
 
                 dialog.hide();
             }),
         );
 
+        // 更新全部订阅
+        let sender = s.sender.clone();
+        s.refresh_all_button.connect_clicked(move |_| {
+            sender.send(Action::RefreshAllSubs).unwrap_or(());
+        });
+
         // 扫码按钮
         let sender = s.sender.clone();
         s.qrcode_button.connect_clicked(move |_| {
             sender.send(Action::Qrcode).unwrap_or(());
         });
 
+        // 从图片导入二维码
+        let sender = s.sender.clone();
+        s.qrcode_file_button.connect_clicked(move |_| {
+            sender.send(Action::QrcodeFile).unwrap_or(());
+        });
+
+        // 从剪贴板导入二维码
+        let sender = s.sender.clone();
+        s.qrcode_paste_button.connect_clicked(move |_| {
+            sender.send(Action::QrcodeClipboard).unwrap_or(());
+        });
+
+        // 关闭二维码窗口时隐藏
+        s.qrcode_dialog
+            .dialog
+            .connect_delete_event(|dialog, _| dialog.hide_on_delete());
+
+        // 保存二维码
+        let dialog_weak = s.qrcode_dialog.dialog.downgrade();
+        let headerbar_weak = s.qrcode_dialog.headerbar.downgrade();
+        let pixbuf = s.qrcode_dialog.pixbuf.clone();
+        let sender_clone = s.sender.clone();
+        s.qrcode_dialog
+            .save_button
+            .connect_clicked(clone!(dialog_weak,headerbar_weak=>move|_| {
+                let dialog = upgrade_weak!(dialog_weak);
+                let name = upgrade_weak!(headerbar_weak)
+                    .get_title()
+                    .map(|title| title.to_string())
+                    .unwrap_or_default();
+                if let Some(pixbuf) = pixbuf.borrow().as_ref() {
+                    let chooser = FileChooserNative::new(
+                        Some("保存二维码"),
+                        Some(&dialog),
+                        FileChooserAction::Save,
+                        Some("保存"),
+                        Some("取消"),
+                    );
+                    chooser.set_current_name(format!("{}.png", name));
+                    chooser.set_do_overwrite_confirmation(true);
+                    if chooser.run() == ResponseType::Accept {
+                        if let Some(path) = chooser.get_filename() {
+                            let notice = if pixbuf.savev(&path, "png", &[]).is_ok() {
+                                "二维码已保存!"
+                            } else {
+                                "保存二维码失败!"
+                            };
+                            sender_clone.send(Action::ShowNotice(notice.to_owned())).unwrap_or(());
+                        }
+                    }
+                }
+            }));
+
+        // 日志按钮
+        let log_dialog = s.log_dialog.clone();
+        s.log_button.connect_clicked(move |_| {
+            log_dialog.show();
+        });
+
+        // 关闭日志窗口时隐藏
+        s.log_dialog
+            .dialog
+            .connect_delete_event(|dialog, _| dialog.hide_on_delete());
+
+        // 复制全部日志
+        let sender_clone = s.sender.clone();
+        s.log_dialog.copy_button.connect_clicked(move |_| {
+            let clipboard = gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD);
+            clipboard.set_text(&logger::content());
+            sender_clone
+                .send(Action::ShowNotice("已复制日志到剪贴板!".to_owned()))
+                .unwrap_or(());
+        });
+
+        // 保存日志
+        let dialog_weak = s.log_dialog.dialog.downgrade();
+        let sender_clone = s.sender.clone();
+        s.log_dialog
+            .save_button
+            .connect_clicked(clone!(dialog_weak=>move|_| {
+                let dialog = upgrade_weak!(dialog_weak);
+                let chooser = FileChooserNative::new(
+                    Some("保存日志"),
+                    Some(&dialog),
+                    FileChooserAction::Save,
+                    Some("保存"),
+                    Some("取消"),
+                );
+                chooser.set_current_name("ssr-local.log");
+                chooser.set_do_overwrite_confirmation(true);
+                if chooser.run() == ResponseType::Accept {
+                    if let Some(path) = chooser.get_filename() {
+                        let notice = if fs::write(&path, logger::content()).is_ok() {
+                            "日志已保存!"
+                        } else {
+                            "保存日志失败!"
+                        };
+                        sender_clone.send(Action::ShowNotice(notice.to_owned())).unwrap_or(());
+                    }
+                }
+            }));
+
+        // 偏好设置
+        let preferences_dialog = s.preferences_dialog.clone();
+        s.preferences_button.connect_clicked(move |_| {
+            preferences_dialog.fill(&Data::new().get_settings());
+            preferences_dialog.dialog.run();
+            preferences_dialog.dialog.hide();
+        });
+
+        // 关闭偏好设置窗口时隐藏
+        s.preferences_dialog
+            .dialog
+            .connect_delete_event(|dialog, _| dialog.hide_on_delete());
+
+        // 取消偏好设置
+        let dialog_weak = s.preferences_dialog.dialog.downgrade();
+        s.preferences_dialog
+            .cancel_button
+            .connect_clicked(clone!(dialog_weak=>move|_| {
+                let dialog = upgrade_weak!(dialog_weak);
+                dialog.hide();
+            }));
+
+        // 保存偏好设置
+        let preferences_dialog = s.preferences_dialog.clone();
+        let sender_clone = s.sender.clone();
+        s.preferences_dialog
+            .finished_button
+            .connect_clicked(move |_| {
+                let mut db = Data::new();
+                let settings = preferences_dialog.read(db.get_settings());
+                let notice = if db.set_settings(&settings).is_ok() {
+                    "设置已保存!"
+                } else {
+                    "保存设置失败!"
+                };
+                sender_clone
+                    .send(Action::ShowNotice(notice.to_owned()))
+                    .unwrap_or(());
+                preferences_dialog.dialog.hide();
+            });
+
         // 设置关于窗口版本号
         s.about_dialog.set_version(Some(APP_VERSION));
 
@@ -405,7 +790,258 @@ impl Header {
             }));
     }
 
-    pub fn disc_button_active(&self) {
-        self.disc_button.set_active(true);
+    // 连接中, 本地端口就绪前只能断开
+    pub fn set_connecting(&self) {
+        self.conn_button.set_label("连接中…");
+        self.conn_button.set_sensitive(false);
+    }
+
+    // 更新连接状态, 失败时切换回断开并关闭 ssr-local
+    pub fn set_connected(&self, connected: bool) {
+        self.conn_button.set_sensitive(true);
+        if connected {
+            self.conn_button.set_label("已连接");
+        } else {
+            self.disc_button.set_active(true);
+        }
+    }
+
+    // 是否处于已连接状态
+    pub fn is_connected(&self) -> bool {
+        self.conn_button.get_active() && self.conn_button.is_sensitive()
+    }
+
+    // 打开订阅设置窗口
+    pub fn show_sub_dialog(&self, group_id: u64, url: &str, options: &SubOptions) {
+        let dialog = &self.subscription_dialog;
+        dialog.edit_id.replace(Some(group_id));
+        dialog.fill(url, options);
+        dialog.sub_button.set_label("保存");
+        // 由消息循环调用, 不能使用 run() 阻塞
+        dialog.dialog.show();
+    }
+
+    // 打开编辑窗口
+    pub fn show_edit_dialog(&self, node_id: u64, group: &str, config: &SsrConfig, from_sub: bool) {
+        let dialog = &self.manual_setting_dialog;
+        dialog.fill(group, config);
+        dialog.group_entry.set_sensitive(false);
+        dialog.sub_warning_label.set_visible(from_sub);
+        dialog.edit_id.replace(Some(node_id));
+        // 由消息循环调用, 不能使用 run() 阻塞
+        dialog.dialog.show();
+    }
+
+    // 显示节点二维码
+    pub fn show_qrcode_dialog(&self, config: &SsrConfig) -> bool {
+        let dialog = &self.qrcode_dialog;
+        if let Some(pixbuf) = qrcode_pixbuf(&ssr_url_encode(config)) {
+            dialog.image.set_from_pixbuf(Some(&pixbuf));
+            dialog.pixbuf.replace(Some(pixbuf));
+            dialog.headerbar.set_title(Some(&config.remarks));
+            dialog.dialog.show();
+            return true;
+        }
+        false
+    }
+}
+
+impl ManualSettingDialog {
+    // 清空输入内容
+    fn clear(&self) {
+        self.fill("", &SsrConfig::default().set_remarks(""));
+        self.group_entry.set_sensitive(true);
+        self.sub_warning_label.set_visible(false);
+        self.edit_id.replace(None);
+    }
+
+    // 填充配置内容
+    fn fill(&self, group: &str, config: &SsrConfig) {
+        self.group_entry.set_text(group);
+        self.configname_entry.set_text(&config.remarks);
+        self.server_entry.set_text(&config.remote_addr);
+        self.remote_port_entry.set_text(&config.remote_port);
+        self.local_port_entry.set_text(&config.local_port);
+        self.password_entry.set_text(&config.password);
+        set_combo_active(&self.method_combo, &METHOD_LIST, &config.method);
+        set_combo_active(&self.protocol_combo, &PROTOCOL_LIST, &config.protocol);
+        self.protoparam_entry.set_text(&config.protoparam);
+        set_combo_active(&self.obfs_combo, &OBFS_LIST, &config.obfs);
+        self.obfsparam_entry.set_text(&config.obfsparam);
+    }
+}
+
+// 生成二维码图片
+fn qrcode_pixbuf(text: &str) -> Option<Pixbuf> {
+    // 每个模块的像素及四周留白的模块数
+    const SCALE: usize = 6;
+    const BORDER: usize = 4;
+    let code = QrCode::new(text).ok()?;
+    let width = code.width();
+    let colors = code.to_colors();
+    let size = (width + BORDER * 2) * SCALE;
+    let mut data = vec![255u8; size * size * 3];
+    for (i, color) in colors.iter().enumerate() {
+        if *color != Color::Dark {
+            continue;
+        }
+        let (x, y) = ((i % width + BORDER) * SCALE, (i / width + BORDER) * SCALE);
+        for row in y..y + SCALE {
+            let start = (row * size + x) * 3;
+            data[start..start + SCALE * 3]
+                .iter_mut()
+                .for_each(|b| *b = 0);
+        }
+    }
+    Some(Pixbuf::from_mut_slice(
+        data,
+        Colorspace::Rgb,
+        false,
+        8,
+        size as i32,
+        size as i32,
+        (size * 3) as i32,
+    ))
+}
+
+// 按名称选中下拉框条目, 列表中没有的值 (如 aes-256-gcm) 以自身为 ID 追加后选中, 不丢弃节点原有配置
+fn set_combo_active(combo: &ComboBoxText, list: &HashMap<u8, &'static str>, value: &str) {
+    if value.is_empty() {
+        combo.set_active(None);
+        return;
+    }
+    match list.iter().find(|(_, v)| **v == value) {
+        Some((id, _)) => {
+            combo.set_active_id(Some(&id.to_string()));
+        }
+        None => {
+            if !combo.set_active_id(Some(value)) {
+                combo.append(Some(value), value);
+                combo.set_active_id(Some(value));
+            }
+        }
+    }
+}
+
+impl LogDialog {
+    // 显示日志窗口, 窗口可见期间定时刷新内容
+    fn show(&self) {
+        if self.dialog.get_visible() {
+            self.dialog.present();
+            return;
+        }
+        self.refresh();
+        self.dialog.show();
+        let s = self.clone();
+        let version = Cell::new(logger::version());
+        glib::timeout_add_local(500, move || {
+            if !s.dialog.get_visible() {
+                return glib::Continue(false);
+            }
+            if version.get() != logger::version() {
+                version.set(logger::version());
+                s.refresh();
+            }
+            glib::Continue(true)
+        });
+    }
+
+    // 刷新日志内容并滚动到末尾
+    fn refresh(&self) {
+        if let Some(buffer) = self.textview.get_buffer() {
+            buffer.set_text(&logger::content());
+            let mut end = buffer.get_end_iter();
+            self.textview.scroll_to_iter(&mut end, 0.0, false, 0.0, 1.0);
+        }
+    }
+}
+
+impl SubDialog {
+    fn fill(&self, url: &str, options: &SubOptions) {
+        self.url_entry.set_text(url);
+        self.user_agent_entry.set_text(&options.user_agent);
+        if let Some(buffer) = self.headers_textview.get_buffer() {
+            let headers = options
+                .headers
+                .iter()
+                .map(|(name, value)| format!("{}: {}", name, value))
+                .collect::<Vec<String>>()
+                .join("\n");
+            buffer.set_text(&headers);
+        }
+        self.timeout_spin.set_value(f64::from(options.timeout));
+        self.retries_spin.set_value(f64::from(options.retries));
+        self.proxy_check.set_active(options.use_proxy);
+    }
+
+    // 读取下载选项, 请求头每行一个, 格式为 名称: 值
+    fn read(&self) -> SubOptions {
+        let headers = self
+            .headers_textview
+            .get_buffer()
+            .and_then(|buffer| {
+                let (start, end) = buffer.get_bounds();
+                buffer.get_text(&start, &end, false)
+            })
+            .map(|text| {
+                text.lines()
+                    .filter_map(|line| {
+                        let mut parts = line.splitn(2, ':');
+                        let name = parts.next()?.trim();
+                        let value = parts.next()?.trim();
+                        if name.is_empty() {
+                            None
+                        } else {
+                            Some((name.to_owned(), value.to_owned()))
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        SubOptions {
+            user_agent: self.user_agent_entry.get_text().trim().to_owned(),
+            headers,
+            timeout: self.timeout_spin.get_value_as_int() as u32,
+            retries: self.retries_spin.get_value_as_int() as u32,
+            use_proxy: self.proxy_check.get_active(),
+        }
+    }
+}
+
+impl PreferencesDialog {
+    // 填充设置内容
+    fn fill(&self, settings: &Settings) {
+        self.auto_restart_switch.set_active(settings.auto_restart);
+        self.max_restarts_spin
+            .set_value(f64::from(settings.max_restarts));
+        self.test_url_entry.set_text(&settings.test_url);
+        self.speed_concurrency_spin
+            .set_value(f64::from(settings.speed_concurrency));
+        self.auto_select_interval_spin
+            .set_value(f64::from(settings.auto_select_interval));
+        self.auto_select_threshold_spin
+            .set_value(f64::from(settings.auto_select_threshold));
+        self.health_check_switch.set_active(settings.health_check);
+        self.health_check_interval_spin
+            .set_value(f64::from(settings.health_check_interval));
+        self.health_check_failures_spin
+            .set_value(f64::from(settings.health_check_failures));
+    }
+
+    // 读取设置内容, 未在窗口中显示的设置保持不变
+    fn read(&self, mut settings: Settings) -> Settings {
+        settings.auto_restart = self.auto_restart_switch.get_active();
+        settings.max_restarts = self.max_restarts_spin.get_value_as_int() as u32;
+        let test_url = self.test_url_entry.get_text().trim().to_owned();
+        if !test_url.is_empty() {
+            settings.test_url = test_url;
+        }
+        settings.speed_concurrency = self.speed_concurrency_spin.get_value_as_int() as u32;
+        settings.auto_select_interval = self.auto_select_interval_spin.get_value_as_int() as u32;
+        settings.auto_select_threshold = self.auto_select_threshold_spin.get_value_as_int() as u32;
+        settings.health_check = self.health_check_switch.get_active();
+        settings.health_check_interval = self.health_check_interval_spin.get_value_as_int() as u32;
+        settings.health_check_failures = self.health_check_failures_spin.get_value_as_int() as u32;
+        settings
     }
 }